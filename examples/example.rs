@@ -5,6 +5,10 @@ use jsonrpc::simple_http::{self, SimpleHttpTransport};
 use std::time::{Duration, SystemTime};
 use std::{env, time::SystemTimeError};
 
+use bitcoin_node_query::{
+    get_analytic_money_supply, get_block_heights_over_last_24_hours,
+    get_pool_shares_for_block_range, PoolDefinitions, SegwitAdoptionOptions,
+};
 use bitcoin_node_query::{
     get_average_block_time_for_last_2016_blocks,
     get_average_block_time_for_since_last_difficulty_adjustement,
@@ -64,6 +68,12 @@ fn main() {
     // // let total_money_supply = get_total_money_supply(&client);
     // // println!("TOTAL MONEY SUPPLY: {:#?}", total_money_supply);
 
+    // let analytic_money_supply = get_analytic_money_supply(&client);
+    // println!(
+    //     "ANALYTIC MONEY SUPPLY: {:#?} btc",
+    //     analytic_money_supply as f64 / 100_000_000.0
+    // );
+
     // let chain_size = get_chain_size(&client);
     // let chain_size_in_gbs = chain_size as f64 / 1_000_000_000.0;
     // println!("CHAIN SIZE: {:#?}GB", chain_size_in_gbs);
@@ -191,40 +201,62 @@ fn main() {
 
     // I expect about 37.4% Script hash transactions over the last 90 days
     // SEGWIT ADOPTION
-    // TODO: check the is_segwith_adress function and make sure it's exactly how you want to
-    // define what segwith type you're looking for
-    let (
-        percent_of_transactions_with_a_segwit_vout,
-        percent_of_transactions_with_a_segwit_vin_or_vout,
-        percent_based_on_transaction_hexes,
-        percent_of_payments_spending_segwit_per_day,
-        percent_of_segwit_spending_transactions_per_day,
-    ) = get_percent_of_vouts_used_segwit_over_last_24_hours(&client);
+    let segwit_adoption_report = get_percent_of_vouts_used_segwit_over_last_24_hours(
+        &client,
+        &SegwitAdoptionOptions::default(),
+    );
     println!(
-        "segwit percent (vouts): {:#?}",
-        percent_of_transactions_with_a_segwit_vout
+        "segwit share (vouts): {:#?}",
+        segwit_adoption_report
+            .transactions_with_a_segwit_vout
+            .and_then(|share| share.share())
     );
     println!(
-        "segwit percent (vouts or vins): {:#?}",
-        percent_of_transactions_with_a_segwit_vin_or_vout,
+        "segwit share (vouts or vins): {:#?}",
+        segwit_adoption_report
+            .transactions_with_a_segwit_vin_or_vout
+            .and_then(|share| share.share())
     );
     // https://bitbo.io/
     println!(
-        "segwit percent (transaction_hexes): {:#?}",
-        percent_based_on_transaction_hexes
+        "segwit share (transaction_hexes): {:#?}",
+        segwit_adoption_report
+            .transactions_with_witness_data
+            .and_then(|share| share.share())
     );
     // https://transactionfee.info/charts/payments-spending-segwit/
     println!(
-        "percent of payments spending segwit_per_day: {:#?}",
-        percent_of_payments_spending_segwit_per_day
+        "share of payments spending segwit per day: {:#?}",
+        segwit_adoption_report
+            .payments_spending_segwit
+            .and_then(|share| share.share())
     );
     // https://transactionfee.info/charts/transactions-spending-segwit/
     println!(
-        "percent of segwit spending transactions per day: {:#?}",
-        percent_of_segwit_spending_transactions_per_day
+        "share of segwit spending transactions per day: {:#?}",
+        segwit_adoption_report
+            .segwit_spending_transactions
+            .and_then(|share| share.share())
     );
+    println!(
+        "nested segwit, native segwit v0 and taproot split of spent inputs: {:#?}",
+        segwit_adoption_report.spent_input_split
+    );
+    if let Some(error) = &segwit_adoption_report.prevout_error {
+        println!("segwit vin numbers left out: {}", error);
+    }
     println!("total money supply: {:#?}", get_total_money_supply(&client));
     println!("utxo set size: {:#?}", get_utxo_set_size(&client));
     println!("total money supply: {:#?}", get_total_money_supply(&client));
     println!("utxo set size: {:#?}", get_utxo_set_size(&client));
+
+    // // takes a long time
+    // let pool_definitions =
+    //     PoolDefinitions::from_file("pools.json").expect("failed to load pool definitions");
+    // let pool_shares = get_pool_shares_for_block_range(
+    //     &client,
+    //     &pool_definitions,
+    //     get_block_heights_over_last_24_hours(&client),
+    // );
+    // println!("POOL SHARES OVER LAST 24 HOURS: {:#?}", pool_shares);
 }
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::{get_block_hash::GetBlockHashCommand, CallableCommand};
+use jsonrpc::serde_json::value::to_raw_value;
+use serde::Deserialize;
+
+use crate::fee_rates::FeeRateInterval;
+use crate::{btc_to_sats, get_block_heights_over_last_24_hours, Client};
+
+// payments and change, or more
+pub const BATCH_MIN_OUTPUTS: u64 = 3;
+pub const CONSOLIDATION_MIN_INPUTS: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TransactionShape {
+    // a payment, and usually change
+    SimplePayment,
+    // one-to-many, like exchange withdrawals
+    BatchedPayout,
+    // many-to-one, usually done while fees are low
+    Consolidation,
+    // anything else, like coinjoins (many-to-many)
+    Other,
+}
+
+impl TransactionShape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionShape::SimplePayment => "simple payment",
+            TransactionShape::BatchedPayout => "batched payout",
+            TransactionShape::Consolidation => "consolidation",
+            TransactionShape::Other => "other",
+        }
+    }
+}
+
+// All fees in sats and fee rates in sat/vB.
+#[derive(Debug, Clone)]
+pub struct TransactionShapeStats {
+    pub shape: TransactionShape,
+    pub transactions_count: u64,
+    pub inputs_count: u64,
+    pub outputs_count: u64,
+    pub weight: u64,
+    // between 0.0 and 100.0, of the weight of all the transactions of the window, coinbase aside
+    pub percent_of_block_space: f64,
+    pub fees: u64,
+    pub average_fee_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransactionShapeWindow {
+    // unix timestamp of the start of the interval
+    pub start_time: u64,
+    pub blocks_count: u64,
+    // excludes coinbase transactions, and the ones without fee
+    pub transactions_count: u64,
+    // left out of the stats, as the node didn't return their fee (missing undo data on pruned
+    // nodes)
+    pub transactions_without_fee_count: u64,
+    // in sat/vB, of all the transactions, to compare the shapes with how busy the network was
+    pub average_fee_rate: f64,
+    // ordered by shape, shapes without transactions left out
+    pub shapes: Vec<TransactionShapeStats>,
+}
+
+// OP_RETURN outputs aren't counted, as they don't pay anyone.
+pub fn get_transaction_shape(inputs_count: u64, outputs_count: u64) -> TransactionShape {
+    if inputs_count >= CONSOLIDATION_MIN_INPUTS && outputs_count == 1 {
+        TransactionShape::Consolidation
+    } else if outputs_count >= BATCH_MIN_OUTPUTS && inputs_count < outputs_count {
+        TransactionShape::BatchedPayout
+    } else if inputs_count < CONSOLIDATION_MIN_INPUTS && outputs_count <= 2 {
+        TransactionShape::SimplePayment
+    } else {
+        TransactionShape::Other
+    }
+}
+
+#[derive(Deserialize)]
+struct ScriptPubKeyResponse {
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+#[derive(Deserialize)]
+struct VoutResponse {
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: ScriptPubKeyResponse,
+}
+
+// only counted
+#[derive(Deserialize)]
+struct VinResponse {}
+
+#[derive(Deserialize)]
+struct TransactionResponse {
+    weight: u64,
+    // in btc, missing for the coinbase, and when the node lacks the undo data of the block
+    fee: Option<f64>,
+    vin: Vec<VinResponse>,
+    vout: Vec<VoutResponse>,
+}
+
+// Not using GetBlockCommand, as its transactions don't include the fee.
+#[derive(Deserialize)]
+struct BlockResponse {
+    time: u64,
+    tx: Vec<TransactionResponse>,
+}
+
+fn get_block_at_height(client: &Client, height: u64) -> BlockResponse {
+    let blockhash = GetBlockHashCommand::new(height)
+        .call(&client.bitcoind_request_client)
+        .unwrap()
+        .0;
+    client
+        .call(
+            "getblock",
+            &[
+                to_raw_value(&blockhash.0).unwrap(),
+                to_raw_value(&2).unwrap(),
+            ],
+        )
+        .unwrap()
+}
+
+// blocks count, transactions without fee count, and stats by shape
+type WindowTotals = (u64, u64, BTreeMap<TransactionShape, TransactionShapeStats>);
+
+fn get_fee_rate(fees: u64, weight: u64) -> f64 {
+    if weight == 0 {
+        0.0
+    } else {
+        fees as f64 / (weight as f64 / 4.0)
+    }
+}
+
+// takes a long time
+// Groups the blocks by their timestamp. Ordered by time.
+pub fn get_transaction_shape_windows_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    interval: FeeRateInterval,
+) -> Vec<TransactionShapeWindow> {
+    let mut windows: BTreeMap<u64, WindowTotals> = BTreeMap::new();
+    for height in heights {
+        let block = get_block_at_height(client, height);
+        let start_time = block.time - block.time % interval.seconds();
+        let (blocks_count, transactions_without_fee_count, stats_by_shape) =
+            windows.entry(start_time).or_default();
+        *blocks_count += 1;
+        for transaction in block.tx.iter().skip(1) {
+            let fee = match transaction.fee {
+                Some(fee) => btc_to_sats(fee),
+                None => {
+                    *transactions_without_fee_count += 1;
+                    continue;
+                }
+            };
+            let inputs_count = transaction.vin.len() as u64;
+            let outputs_count = transaction
+                .vout
+                .iter()
+                .filter(|vout| vout.script_pub_key.type_ != "nulldata")
+                .count() as u64;
+            let shape = get_transaction_shape(inputs_count, outputs_count);
+            let stats = stats_by_shape
+                .entry(shape)
+                .or_insert_with(|| TransactionShapeStats {
+                    shape,
+                    transactions_count: 0,
+                    inputs_count: 0,
+                    outputs_count: 0,
+                    weight: 0,
+                    percent_of_block_space: 0.0,
+                    fees: 0,
+                    average_fee_rate: 0.0,
+                });
+            stats.transactions_count += 1;
+            stats.inputs_count += inputs_count;
+            stats.outputs_count += outputs_count;
+            stats.weight += transaction.weight;
+            stats.fees += fee;
+        }
+    }
+    windows
+        .into_iter()
+        .map(
+            |(start_time, (blocks_count, transactions_without_fee_count, stats_by_shape))| {
+                let weight: u64 = stats_by_shape.values().map(|stats| stats.weight).sum();
+                let fees: u64 = stats_by_shape.values().map(|stats| stats.fees).sum();
+                TransactionShapeWindow {
+                    start_time,
+                    blocks_count,
+                    transactions_count: stats_by_shape
+                        .values()
+                        .map(|stats| stats.transactions_count)
+                        .sum(),
+                    transactions_without_fee_count,
+                    average_fee_rate: get_fee_rate(fees, weight),
+                    shapes: stats_by_shape
+                        .into_values()
+                        .map(|mut stats| {
+                            stats.percent_of_block_space =
+                                stats.weight as f64 / weight as f64 * 100.0;
+                            stats.average_fee_rate = get_fee_rate(stats.fees, stats.weight);
+                            stats
+                        })
+                        .collect(),
+                }
+            },
+        )
+        .collect()
+}
+
+// takes a long time
+pub fn get_transaction_shape_windows_over_last_24_hours(
+    client: &Client,
+    interval: FeeRateInterval,
+) -> Vec<TransactionShapeWindow> {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_transaction_shape_windows_for_block_range(client, heights, interval)
+}
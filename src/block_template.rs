@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bitcoind_request::command::{get_block_hash::GetBlockHashCommand, CallableCommand};
+use jsonrpc::serde_json::{json, value::to_raw_value};
+use serde::Deserialize;
+
+use crate::mempool::{get_mempool_entries, get_projected_blocks};
+use crate::mining_pool::{get_block_pool_from_coinbase_transaction, UNKNOWN_POOL_NAME};
+use crate::{
+    get_block_height, get_block_with_transactions_at_height, get_total_fee_for_block_at_height,
+    get_transactions_of_block, Client, PoolDefinitions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockTemplateSource {
+    // first block projected from our mempool
+    ProjectedFromMempool,
+    // getblocktemplate of our node
+    GetBlockTemplate,
+}
+
+#[derive(Debug, Clone)]
+pub struct TemplateTransaction {
+    pub txid: String,
+    // in sats
+    pub fee: u64,
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub source: BlockTemplateSource,
+    // height of the block the template is for
+    pub height: u64,
+    pub previous_blockhash: String,
+    // local time the template was captured
+    pub time: i64,
+    // excludes the coinbase
+    pub transactions: Vec<TemplateTransaction>,
+}
+
+impl BlockTemplate {
+    pub fn fees(&self) -> u64 {
+        self.transactions
+            .iter()
+            .map(|transaction| transaction.fee)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockTemplateAudit {
+    pub height: u64,
+    pub blockhash: String,
+    pub pool_name: Option<String>,
+    // false when the template was built on another block than the one that got mined on, in which
+    // case differences are expected
+    pub same_previous_block: bool,
+    pub expected_transactions_count: u64,
+    // excludes the coinbase
+    pub mined_transactions_count: u64,
+    // in the template but not in the block. Transactions that are repeatedly left out can point to
+    // censorship.
+    pub missing_transactions: Vec<TemplateTransaction>,
+    // in the block but not in the template, like transactions paid for out of band or that never
+    // reached our mempool
+    pub unexpected_txids: Vec<String>,
+    // all fees in sats
+    pub expected_fees: u64,
+    pub mined_fees: u64,
+    // mined_fees - expected_fees
+    pub fee_difference: i64,
+    // between 0.0 and 1.0, shared transactions over all the transactions of either the template or
+    // the block
+    pub similarity: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolTemplateAuditSummary {
+    pub pool_name: String,
+    pub blocks_count: u64,
+    pub average_similarity: f64,
+    pub missing_transactions_count: u64,
+    pub unexpected_transactions_count: u64,
+    pub total_fee_difference: i64,
+}
+
+#[derive(Deserialize)]
+struct BlockTemplateTransactionResponse {
+    txid: String,
+    fee: u64,
+    weight: u64,
+}
+
+#[derive(Deserialize)]
+struct BlockTemplateResponse {
+    height: u64,
+    previousblockhash: String,
+    transactions: Vec<BlockTemplateTransactionResponse>,
+}
+
+pub fn get_block_template(client: &Client) -> BlockTemplate {
+    let response: BlockTemplateResponse = client
+        .call(
+            "getblocktemplate",
+            &[to_raw_value(&json!({ "rules": ["segwit"] })).unwrap()],
+        )
+        .unwrap();
+    BlockTemplate {
+        source: BlockTemplateSource::GetBlockTemplate,
+        height: response.height,
+        previous_blockhash: response.previousblockhash,
+        time: chrono::offset::Utc::now().timestamp(),
+        transactions: response
+            .transactions
+            .into_iter()
+            .map(|transaction| TemplateTransaction {
+                txid: transaction.txid,
+                fee: transaction.fee,
+                weight: transaction.weight,
+            })
+            .collect(),
+    }
+}
+
+pub fn get_projected_block_template(client: &Client) -> BlockTemplate {
+    let tip_height = get_block_height(client);
+    let blockhash = GetBlockHashCommand::new(tip_height)
+        .call(&client.bitcoind_request_client)
+        .unwrap()
+        .0;
+    let entries = get_mempool_entries(client);
+    let transactions = match get_projected_blocks(&entries, 1).first() {
+        Some(projected_block) => projected_block
+            .txids
+            .iter()
+            .map(|txid| TemplateTransaction {
+                txid: txid.clone(),
+                fee: entries[txid].modified_fee,
+                weight: entries[txid].weight,
+            })
+            .collect(),
+        None => vec![],
+    };
+    BlockTemplate {
+        source: BlockTemplateSource::ProjectedFromMempool,
+        height: tip_height + 1,
+        previous_blockhash: blockhash.0,
+        time: chrono::offset::Utc::now().timestamp(),
+        transactions,
+    }
+}
+
+// Compares the block mined at the template's height with the template.
+pub fn get_block_template_audit(
+    client: &Client,
+    template: &BlockTemplate,
+    definitions: Option<&PoolDefinitions>,
+) -> BlockTemplateAudit {
+    let block = get_block_with_transactions_at_height(client, template.height);
+    let same_previous_block =
+        block.previousblockhash.as_deref() == Some(template.previous_blockhash.as_str());
+    let transactions = get_transactions_of_block(block.tx);
+    let pool_name = definitions.and_then(|definitions| {
+        get_block_pool_from_coinbase_transaction(
+            definitions,
+            template.height,
+            block.hash.clone(),
+            block.time,
+            transactions.first().unwrap(),
+        )
+        .pool_name
+    });
+    let mined_txids: HashSet<&str> = transactions
+        .iter()
+        .skip(1)
+        .map(|transaction| transaction.txid.as_str())
+        .collect();
+    let expected_txids: HashSet<&str> = template
+        .transactions
+        .iter()
+        .map(|transaction| transaction.txid.as_str())
+        .collect();
+    let missing_transactions: Vec<TemplateTransaction> = template
+        .transactions
+        .iter()
+        .filter(|transaction| !mined_txids.contains(transaction.txid.as_str()))
+        .cloned()
+        .collect();
+    let unexpected_txids: Vec<String> = transactions
+        .iter()
+        .skip(1)
+        .filter(|transaction| !expected_txids.contains(transaction.txid.as_str()))
+        .map(|transaction| transaction.txid.clone())
+        .collect();
+    let shared_transactions_count = mined_txids.intersection(&expected_txids).count();
+    let all_transactions_count = mined_txids.union(&expected_txids).count();
+    let expected_fees = template.fees();
+    let mined_fees = get_total_fee_for_block_at_height(client, template.height);
+    BlockTemplateAudit {
+        height: template.height,
+        blockhash: block.hash,
+        pool_name,
+        same_previous_block,
+        expected_transactions_count: template.transactions.len() as u64,
+        mined_transactions_count: mined_txids.len() as u64,
+        missing_transactions,
+        unexpected_txids,
+        expected_fees,
+        mined_fees,
+        fee_difference: mined_fees as i64 - expected_fees as i64,
+        // two empty blocks are identical
+        similarity: if all_transactions_count == 0 {
+            1.0
+        } else {
+            shared_transactions_count as f64 / all_transactions_count as f64
+        },
+    }
+}
+
+// Blocks that couldn't be attributed are grouped under "Unknown". Sorted by pool name.
+pub fn get_pool_template_audit_summaries(
+    audits: &[BlockTemplateAudit],
+) -> Vec<PoolTemplateAuditSummary> {
+    let mut audits_by_pool_name: BTreeMap<&str, Vec<&BlockTemplateAudit>> = BTreeMap::new();
+    for audit in audits {
+        let pool_name = audit.pool_name.as_deref().unwrap_or(UNKNOWN_POOL_NAME);
+        audits_by_pool_name
+            .entry(pool_name)
+            .or_default()
+            .push(audit);
+    }
+    audits_by_pool_name
+        .into_iter()
+        .map(|(pool_name, audits)| PoolTemplateAuditSummary {
+            pool_name: pool_name.to_string(),
+            blocks_count: audits.len() as u64,
+            average_similarity: audits.iter().map(|audit| audit.similarity).sum::<f64>()
+                / audits.len() as f64,
+            missing_transactions_count: audits
+                .iter()
+                .map(|audit| audit.missing_transactions.len() as u64)
+                .sum(),
+            unexpected_transactions_count: audits
+                .iter()
+                .map(|audit| audit.unexpected_txids.len() as u64)
+                .sum(),
+            total_fee_difference: audits.iter().map(|audit| audit.fee_difference).sum(),
+        })
+        .collect()
+}
+
+// Captures a template for the next block every time it's polled, and audits every block mined
+// since the previous poll against the last template captured for its height. Poll often (every
+// few seconds) so the templates are as close as possible to what miners were working on.
+//
+//   let mut auditor = BlockTemplateAuditor::new(BlockTemplateSource::GetBlockTemplate, None);
+//   loop {
+//       for audit in auditor.poll(&client) {
+//           println!("{:#?}", audit);
+//       }
+//       sleep(Duration::from_secs(5));
+//   }
+pub struct BlockTemplateAuditor {
+    pub source: BlockTemplateSource,
+    pub definitions: Option<PoolDefinitions>,
+    pub audits: Vec<BlockTemplateAudit>,
+    templates_by_height: HashMap<u64, BlockTemplate>,
+    last_seen_height: Option<u64>,
+}
+
+impl BlockTemplateAuditor {
+    pub fn new(source: BlockTemplateSource, definitions: Option<PoolDefinitions>) -> Self {
+        BlockTemplateAuditor {
+            source,
+            definitions,
+            audits: vec![],
+            templates_by_height: HashMap::new(),
+            last_seen_height: None,
+        }
+    }
+
+    // Returns the audits of the blocks mined since the previous poll.
+    pub fn poll(&mut self, client: &Client) -> Vec<BlockTemplateAudit> {
+        let tip_height = get_block_height(client);
+        let mut new_audits = vec![];
+        if let Some(last_seen_height) = self.last_seen_height {
+            for height in last_seen_height + 1..=tip_height {
+                if let Some(template) = self.templates_by_height.remove(&height) {
+                    new_audits.push(get_block_template_audit(
+                        client,
+                        &template,
+                        self.definitions.as_ref(),
+                    ));
+                }
+            }
+        }
+        self.last_seen_height = Some(tip_height);
+        self.templates_by_height
+            .retain(|height, _| *height > tip_height);
+        let template = match self.source {
+            BlockTemplateSource::ProjectedFromMempool => get_projected_block_template(client),
+            BlockTemplateSource::GetBlockTemplate => get_block_template(client),
+        };
+        self.templates_by_height.insert(template.height, template);
+        self.audits.extend(new_audits.iter().cloned());
+        new_audits
+    }
+}
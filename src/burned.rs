@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::get_block::DecodeRawTransactionResponse;
+use chrono::{Datelike, TimeZone, Utc};
+
+use crate::coinbase::{get_claimed_reward, get_subsidy_and_fees_for_block_at_height};
+use crate::script::{decode_hex, is_provably_unspendable_script, OP_RETURN};
+use crate::{
+    btc_to_sats, get_block_with_transactions_at_height, get_transactions_of_block, Client,
+};
+
+// Well known mainnet addresses nobody has the keys to.
+pub const DEFAULT_BURN_ADDRESSES: [&str; 3] = [
+    "1BitcoinEaterAddressDontSendf59kuE",
+    "1111111111111111111114oLvT2",
+    "1CounterpartyXXXXXXXXXXXXXXXUWLpVr",
+];
+
+// All amounts in sats.
+#[derive(Debug, Clone, Default)]
+pub struct BurnedValue {
+    pub op_return: u64,
+    // sent to one of the configured burn addresses. Unlike the other categories, these outputs are
+    // still part of the UTXO set.
+    pub burn_addresses: u64,
+    // other outputs that can never be spent, like scripts over the size limit
+    pub unspendable_scripts: u64,
+    // coinbase rewards miners were allowed to claim but didn't
+    pub unclaimed_rewards: u64,
+}
+
+impl BurnedValue {
+    pub fn total(&self) -> u64 {
+        self.op_return + self.burn_addresses + self.unspendable_scripts + self.unclaimed_rewards
+    }
+    fn add(&mut self, other: &BurnedValue) {
+        self.op_return += other.op_return;
+        self.burn_addresses += other.burn_addresses;
+        self.unspendable_scripts += other.unspendable_scripts;
+        self.unclaimed_rewards += other.unclaimed_rewards;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct YearlyBurnedValue {
+    pub year: i32,
+    pub burned_value: BurnedValue,
+}
+
+#[derive(Debug, Clone)]
+pub struct BurnedValueReport {
+    pub total: BurnedValue,
+    // ordered by year
+    pub by_year: Vec<YearlyBurnedValue>,
+}
+
+fn get_burned_value_of_block_transactions(
+    transactions: &[DecodeRawTransactionResponse],
+    subsidy: u64,
+    fees: u64,
+    burn_addresses: &[&str],
+) -> BurnedValue {
+    let mut burned_value = BurnedValue::default();
+    for vout in transactions
+        .iter()
+        .flat_map(|transaction| transaction.vout.iter())
+    {
+        let script = decode_hex(&vout.script_pub_key.hex);
+        let value = btc_to_sats(vout.value);
+        if script.first() == Some(&OP_RETURN) {
+            burned_value.op_return += value;
+        } else if is_provably_unspendable_script(&script) {
+            burned_value.unspendable_scripts += value;
+        } else if let Some(address) = &vout.script_pub_key.address {
+            if burn_addresses.contains(&address.as_str()) {
+                burned_value.burn_addresses += value;
+            }
+        }
+    }
+    let claimed_reward = get_claimed_reward(transactions.first().unwrap());
+    burned_value.unclaimed_rewards = (subsidy + fees).saturating_sub(claimed_reward);
+    burned_value
+}
+
+pub fn get_burned_value_for_block_at_height(
+    client: &Client,
+    height: u64,
+    burn_addresses: &[&str],
+) -> BurnedValue {
+    let block = get_block_with_transactions_at_height(client, height);
+    let transactions = get_transactions_of_block(block.tx);
+    let (subsidy, fees) = get_subsidy_and_fees_for_block_at_height(client, height);
+    get_burned_value_of_block_transactions(&transactions, subsidy, fees, burn_addresses)
+}
+
+// Pass &DEFAULT_BURN_ADDRESSES (or your own list) as `burn_addresses`.
+// takes a long time
+pub fn get_burned_value_report_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    burn_addresses: &[&str],
+) -> BurnedValueReport {
+    let mut total = BurnedValue::default();
+    let mut burned_value_by_year: BTreeMap<i32, BurnedValue> = BTreeMap::new();
+    for height in heights {
+        let block = get_block_with_transactions_at_height(client, height);
+        let year = Utc.timestamp_opt(block.time as i64, 0).unwrap().year();
+        let transactions = get_transactions_of_block(block.tx);
+        let (subsidy, fees) = get_subsidy_and_fees_for_block_at_height(client, height);
+        let burned_value =
+            get_burned_value_of_block_transactions(&transactions, subsidy, fees, burn_addresses);
+        total.add(&burned_value);
+        burned_value_by_year
+            .entry(year)
+            .or_default()
+            .add(&burned_value);
+    }
+    BurnedValueReport {
+        total,
+        by_year: burned_value_by_year
+            .into_iter()
+            .map(|(year, burned_value)| YearlyBurnedValue { year, burned_value })
+            .collect(),
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::get_block::DecodeRawTransactionResponse;
+
+use crate::prevouts::{Prevout, PrevoutResolver, DEFAULT_PREVOUT_CACHE_CAPACITY};
+use crate::script::decode_hex;
+use crate::script_types::{get_script_type, ScriptType};
+use crate::{btc_to_sats, get_block_heights_over_last_24_hours, Client};
+
+// Outputs at or above this confidence are considered change.
+pub const CHANGE_CONFIDENCE_THRESHOLD: f64 = 0.5;
+
+// 0.0001 btc
+const ROUND_AMOUNT: u64 = 10_000;
+const SECONDS_IN_A_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeHeuristic {
+    // the only output of the same script type as all the inputs
+    ScriptTypeMatchesInputs,
+    // sent back to the script of one of the inputs
+    AddressReuse,
+    // the only output with a non round amount, the others looking like payments
+    RoundPaymentAmount,
+    // last of outputs that aren't sorted (BIP69), as some wallets add the change at the end
+    OutputOrdering,
+    // had the other output been the change, one of the inputs wouldn't have been needed
+    UnnecessaryInput,
+}
+
+impl ChangeHeuristic {
+    // Chances of the output being change when only this heuristic applies.
+    pub fn weight(&self) -> f64 {
+        match self {
+            ChangeHeuristic::ScriptTypeMatchesInputs => 0.6,
+            ChangeHeuristic::AddressReuse => 0.9,
+            ChangeHeuristic::RoundPaymentAmount => 0.5,
+            ChangeHeuristic::OutputOrdering => 0.2,
+            ChangeHeuristic::UnnecessaryInput => 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeOutputLikelihood {
+    pub vout: u64,
+    // between 0.0 and 1.0, combines the weights of the heuristics as independent signals
+    pub confidence: f64,
+    pub heuristics: Vec<ChangeHeuristic>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentDailyStats {
+    // unix timestamp of the start of the day (UTC)
+    pub start_time: u64,
+    pub blocks_count: u64,
+    // excludes coinbase transactions
+    pub transactions_count: u64,
+    pub transactions_with_change_count: u64,
+    // outputs that aren't change nor OP_RETURN
+    pub payments_count: u64,
+    // in sats, sum of the payments
+    pub value_transferred: u64,
+}
+
+impl PaymentDailyStats {
+    fn new(start_time: u64) -> Self {
+        PaymentDailyStats {
+            start_time,
+            blocks_count: 0,
+            transactions_count: 0,
+            transactions_with_change_count: 0,
+            payments_count: 0,
+            value_transferred: 0,
+        }
+    }
+}
+
+fn is_round_amount(value: u64) -> bool {
+    value > 0 && value.is_multiple_of(ROUND_AMOUNT)
+}
+
+// Sorted by amount first, then by script.
+fn is_bip69_sorted(outputs: &[Prevout]) -> bool {
+    outputs.windows(2).all(|pair| {
+        (pair[0].value, decode_hex(&pair[0].script_pub_key_hex))
+            <= (pair[1].value, decode_hex(&pair[1].script_pub_key_hex))
+    })
+}
+
+pub(crate) fn get_outputs_of_transaction(
+    transaction: &DecodeRawTransactionResponse,
+) -> Vec<Prevout> {
+    transaction
+        .vout
+        .iter()
+        .map(|vout| Prevout {
+            value: btc_to_sats(vout.value),
+            script_pub_key_hex: vout.script_pub_key.hex.clone(),
+        })
+        .collect()
+}
+
+// One per output, in order. `inputs` are the outputs spent by the transaction. Transactions with a
+// single payment output (OP_RETURN outputs aside) have no change.
+pub fn get_change_output_likelihoods(
+    inputs: &[Prevout],
+    outputs: &[Prevout],
+) -> Vec<ChangeOutputLikelihood> {
+    let output_script_types: Vec<ScriptType> = outputs
+        .iter()
+        .map(|output| get_script_type(&decode_hex(&output.script_pub_key_hex)))
+        .collect();
+    let candidates: Vec<usize> = (0..outputs.len())
+        .filter(|index| output_script_types[*index] != ScriptType::OpReturn)
+        .collect();
+    let mut heuristics_by_index: Vec<Vec<ChangeHeuristic>> = vec![vec![]; outputs.len()];
+    if candidates.len() > 1 && !inputs.is_empty() {
+        let input_script_types: Vec<ScriptType> = inputs
+            .iter()
+            .map(|input| get_script_type(&decode_hex(&input.script_pub_key_hex)))
+            .collect();
+        if input_script_types
+            .iter()
+            .all(|script_type| *script_type == input_script_types[0])
+        {
+            let matching: Vec<&usize> = candidates
+                .iter()
+                .filter(|index| output_script_types[**index] == input_script_types[0])
+                .collect();
+            if let [index] = matching.as_slice() {
+                heuristics_by_index[**index].push(ChangeHeuristic::ScriptTypeMatchesInputs);
+            }
+        }
+
+        for index in &candidates {
+            if inputs
+                .iter()
+                .any(|input| input.script_pub_key_hex == outputs[*index].script_pub_key_hex)
+            {
+                heuristics_by_index[*index].push(ChangeHeuristic::AddressReuse);
+            }
+        }
+
+        let non_round: Vec<&usize> = candidates
+            .iter()
+            .filter(|index| !is_round_amount(outputs[**index].value))
+            .collect();
+        if let [index] = non_round.as_slice() {
+            heuristics_by_index[**index].push(ChangeHeuristic::RoundPaymentAmount);
+        }
+
+        if !is_bip69_sorted(outputs) {
+            heuristics_by_index[*candidates.last().unwrap()].push(ChangeHeuristic::OutputOrdering);
+        }
+
+        if let ([first, second], true) = (candidates.as_slice(), inputs.len() > 1) {
+            let inputs_value: u64 = inputs.iter().map(|input| input.value).sum();
+            let outputs_value: u64 = outputs.iter().map(|output| output.value).sum();
+            let fee = inputs_value.saturating_sub(outputs_value);
+            let smallest_input_value = inputs.iter().map(|input| input.value).min().unwrap();
+            // whether all the inputs were needed to pay the output
+            let needs_all_inputs =
+                |index: usize| inputs_value - smallest_input_value < outputs[index].value + fee;
+            for (change, payment) in [(*first, *second), (*second, *first)] {
+                if needs_all_inputs(payment) && !needs_all_inputs(change) {
+                    heuristics_by_index[change].push(ChangeHeuristic::UnnecessaryInput);
+                }
+            }
+        }
+    }
+    heuristics_by_index
+        .into_iter()
+        .enumerate()
+        .map(|(index, heuristics)| ChangeOutputLikelihood {
+            vout: index as u64,
+            confidence: 1.0
+                - heuristics
+                    .iter()
+                    .map(|heuristic| 1.0 - heuristic.weight())
+                    .product::<f64>(),
+            heuristics,
+        })
+        .collect()
+}
+
+// The output with the highest confidence, when it reaches CHANGE_CONFIDENCE_THRESHOLD and no
+// other output is as likely.
+pub fn get_likely_change_output(likelihoods: &[ChangeOutputLikelihood]) -> Option<u64> {
+    let highest_confidence = likelihoods
+        .iter()
+        .map(|likelihood| likelihood.confidence)
+        .fold(0.0, f64::max);
+    let most_likely: Vec<&ChangeOutputLikelihood> = likelihoods
+        .iter()
+        .filter(|likelihood| likelihood.confidence == highest_confidence)
+        .collect();
+    match most_likely.as_slice() {
+        [likelihood] if likelihood.confidence >= CHANGE_CONFIDENCE_THRESHOLD => {
+            Some(likelihood.vout)
+        }
+        _ => None,
+    }
+}
+
+// The outputs paying someone: all of them but the change output and the OP_RETURN outputs, which
+// only carry data.
+pub(crate) fn get_payment_outputs(
+    outputs: &[Prevout],
+    change_output: Option<u64>,
+) -> Vec<&Prevout> {
+    outputs
+        .iter()
+        .enumerate()
+        .filter(|(index, output)| {
+            Some(*index as u64) != change_output
+                && get_script_type(&decode_hex(&output.script_pub_key_hex)) != ScriptType::OpReturn
+        })
+        .map(|(_, output)| output)
+        .collect()
+}
+
+// takes a long time
+// Ordered by day.
+pub fn get_payment_stats_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> Vec<PaymentDailyStats> {
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    let mut stats_by_start_time: BTreeMap<u64, PaymentDailyStats> = BTreeMap::new();
+    for height in heights {
+        let block = prevout_resolver.get_block_with_prevouts_at_height(client, height);
+        let start_time = block.time - block.time % SECONDS_IN_A_DAY;
+        let stats = stats_by_start_time
+            .entry(start_time)
+            .or_insert_with(|| PaymentDailyStats::new(start_time));
+        stats.blocks_count += 1;
+        for (transaction, inputs) in block.transactions.iter().zip(block.get_prevouts()).skip(1) {
+            stats.transactions_count += 1;
+            let outputs = get_outputs_of_transaction(transaction);
+            let likelihoods = get_change_output_likelihoods(inputs, &outputs);
+            let change_output = get_likely_change_output(&likelihoods);
+            if change_output.is_some() {
+                stats.transactions_with_change_count += 1;
+            }
+            for output in get_payment_outputs(&outputs, change_output) {
+                stats.payments_count += 1;
+                stats.value_transferred += output.value;
+            }
+        }
+    }
+    stats_by_start_time.into_values().collect()
+}
+
+// takes a long time
+pub fn get_payment_stats_over_last_24_hours(client: &Client) -> Vec<PaymentDailyStats> {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_payment_stats_for_block_range(client, heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P2WPKH_HEX: &str = "00141111111111111111111111111111111111111111";
+    const OTHER_P2WPKH_HEX: &str = "00142222222222222222222222222222222222222222";
+    const P2PKH_HEX: &str = "76a914333333333333333333333333333333333333333388ac";
+    const OTHER_P2PKH_HEX: &str = "76a914444444444444444444444444444444444444444488ac";
+    const OP_RETURN_HEX: &str = "6a0401020304";
+
+    fn output(value: u64, script_pub_key_hex: &str) -> Prevout {
+        Prevout {
+            value,
+            script_pub_key_hex: script_pub_key_hex.to_string(),
+        }
+    }
+
+    fn heuristics_of(likelihoods: &[ChangeOutputLikelihood]) -> Vec<Vec<ChangeHeuristic>> {
+        likelihoods
+            .iter()
+            .map(|likelihood| likelihood.heuristics.clone())
+            .collect()
+    }
+
+    #[test]
+    fn change_matches_the_input_script_type_and_has_a_non_round_amount() {
+        let inputs = [output(100_000, P2WPKH_HEX)];
+        // not sorted by amount, so the last output is more likely to be change
+        let outputs = [output(50_000, P2PKH_HEX), output(49_000, OTHER_P2WPKH_HEX)];
+        let likelihoods = get_change_output_likelihoods(&inputs, &outputs);
+        assert_eq!(
+            heuristics_of(&likelihoods),
+            vec![
+                vec![],
+                vec![
+                    ChangeHeuristic::ScriptTypeMatchesInputs,
+                    ChangeHeuristic::RoundPaymentAmount,
+                    ChangeHeuristic::OutputOrdering,
+                ],
+            ]
+        );
+        assert_eq!(likelihoods[0].confidence, 0.0);
+        // 1 - 0.4 * 0.5 * 0.8
+        assert!((likelihoods[1].confidence - 0.84).abs() < 1e-9);
+        assert_eq!(get_likely_change_output(&likelihoods), Some(1));
+    }
+
+    #[test]
+    fn change_sent_back_to_an_input_script() {
+        let inputs = [output(100_000, P2PKH_HEX)];
+        let outputs = [output(30_000, P2PKH_HEX), output(60_000, OTHER_P2PKH_HEX)];
+        let likelihoods = get_change_output_likelihoods(&inputs, &outputs);
+        assert_eq!(
+            heuristics_of(&likelihoods),
+            vec![vec![ChangeHeuristic::AddressReuse], vec![]]
+        );
+        assert_eq!(get_likely_change_output(&likelihoods), Some(0));
+    }
+
+    #[test]
+    fn change_is_the_output_that_didnt_need_every_input() {
+        let inputs = [output(60_000, P2PKH_HEX), output(50_000, OTHER_P2PKH_HEX)];
+        // paying 100_000 needs both inputs, paying 10_000 would only have needed the first one
+        let outputs = [
+            output(10_000, P2WPKH_HEX),
+            output(100_000, OTHER_P2WPKH_HEX),
+        ];
+        let likelihoods = get_change_output_likelihoods(&inputs, &outputs);
+        assert_eq!(
+            heuristics_of(&likelihoods),
+            vec![vec![ChangeHeuristic::UnnecessaryInput], vec![]]
+        );
+        assert_eq!(get_likely_change_output(&likelihoods), Some(0));
+    }
+
+    #[test]
+    fn single_payment_has_no_change() {
+        let inputs = [output(100_000, P2WPKH_HEX)];
+        let outputs = [output(0, OP_RETURN_HEX), output(99_000, P2WPKH_HEX)];
+        let likelihoods = get_change_output_likelihoods(&inputs, &outputs);
+        assert_eq!(heuristics_of(&likelihoods), vec![vec![], vec![]]);
+        assert_eq!(get_likely_change_output(&likelihoods), None);
+        assert_eq!(get_payment_outputs(&outputs, None), vec![&outputs[1]]);
+    }
+
+    #[test]
+    fn no_change_when_outputs_are_as_likely_or_below_the_threshold() {
+        let inputs = [output(100_000, P2WPKH_HEX)];
+        // both match the input script type and have non round amounts, and are sorted
+        let outputs = [
+            output(15_000, OTHER_P2WPKH_HEX),
+            output(25_000, OTHER_P2WPKH_HEX),
+        ];
+        let likelihoods = get_change_output_likelihoods(&inputs, &outputs);
+        assert_eq!(heuristics_of(&likelihoods), vec![vec![], vec![]]);
+        assert_eq!(get_likely_change_output(&likelihoods), None);
+
+        let likelihoods = [ChangeOutputLikelihood {
+            vout: 1,
+            confidence: ChangeHeuristic::OutputOrdering.weight(),
+            heuristics: vec![ChangeHeuristic::OutputOrdering],
+        }];
+        assert_eq!(get_likely_change_output(&likelihoods), None);
+    }
+
+    #[test]
+    fn payments_leave_out_the_change_and_op_return_outputs() {
+        let outputs = [
+            output(50_000, P2PKH_HEX),
+            output(0, OP_RETURN_HEX),
+            output(49_000, P2WPKH_HEX),
+        ];
+        assert_eq!(get_payment_outputs(&outputs, Some(2)), vec![&outputs[0]]);
+    }
+}
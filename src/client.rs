@@ -2,6 +2,7 @@ use bitcoind_request::{
     client::Client as BitcoindRequestClient, client::Request as BitcoindRequestRequest,
 };
 use jsonrpc::{serde_json::value::RawValue, simple_http, Response as JsonRPCResponse};
+use serde::de::DeserializeOwned;
 
 pub struct Client {
     pub bitcoind_request_client: BitcoindRequestClient,
@@ -35,4 +36,14 @@ impl Client {
         let response = self.bitcoind_request_client.send_request(request.0);
         response
     }
+    // For RPC commands (or arguments) that bitcoind-request doesn't support yet.
+    pub fn call<T: DeserializeOwned>(
+        &self,
+        command: &str,
+        params: &[Box<RawValue>],
+    ) -> Result<T, jsonrpc::Error> {
+        let params = params.to_vec();
+        let request = self.build_request(command, &params);
+        self.send_request(request)?.result()
+    }
 }
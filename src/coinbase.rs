@@ -0,0 +1,467 @@
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::{
+    get_block::{
+        DecodeRawTransactionResponse, GetBlockCommand, GetBlockCommandResponse,
+        GetBlockCommandTransactionResponse, GetBlockCommandVerbosity, Vin,
+    },
+    get_block_hash::GetBlockHashCommand,
+    get_block_stats::{
+        GetBlockStatsCommand, GetBlockStatsCommandResponse, StatsArgumentChoices,
+        TargetBlockArgument,
+    },
+    CallableCommand,
+};
+use jsonrpc::serde_json::value::to_raw_value;
+
+use crate::mining_pool::get_coinbase_script_sig_hex;
+use crate::network::{get_network, Network};
+use crate::script::{
+    decode_hex, decode_reversed_hex, decode_script_number, decode_small_number_opcode, encode_hex,
+    parse_script, to_printable_ascii, ScriptInstruction,
+};
+use crate::sha256::sha256d;
+use crate::{
+    btc_to_sats, get_block_height, get_block_with_transactions_at_height,
+    get_transactions_of_block, Client,
+};
+
+// OP_RETURN, push of 36 bytes, followed by the 0xaa21a9ed commitment header (BIP141).
+const WITNESS_COMMITMENT_SCRIPT_PREFIX: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+#[derive(Debug, Clone)]
+pub struct CoinbaseAudit {
+    pub height: u64,
+    pub blockhash: String,
+    pub txid: String,
+    pub script_sig_hex: String,
+    // height encoded as the first push of the scriptSig
+    pub bip34_height: Option<i64>,
+    // None for blocks mined before BIP34 was enforced
+    pub bip34_height_is_valid: Option<bool>,
+    // data of the push following the height, which is where miners usually put the extranonce, or
+    // all the bytes after the height when they don't parse as script
+    pub extranonce_hex: Option<String>,
+    // printable ascii of the scriptSig after the height push
+    pub tag: String,
+    pub has_witness_transactions: bool,
+    pub witness_commitment_hex: Option<String>,
+    // None when the block has no witness commitment
+    pub witness_commitment_is_valid: Option<bool>,
+    // outputs that aren't OP_RETURNs
+    pub payout_outputs_count: u64,
+    pub claimed_reward: u64,
+    pub subsidy: u64,
+    pub fees: u64,
+}
+
+impl CoinbaseAudit {
+    pub fn allowed_reward(&self) -> u64 {
+        self.subsidy + self.fees
+    }
+    // Sats the miner was allowed to claim but didn't. These are lost forever.
+    pub fn under_claimed_reward(&self) -> u64 {
+        self.allowed_reward().saturating_sub(self.claimed_reward)
+    }
+    pub fn is_under_claimed(&self) -> bool {
+        self.under_claimed_reward() > 0
+    }
+    // A block containing witness transactions must commit to them.
+    pub fn is_missing_witness_commitment(&self) -> bool {
+        self.has_witness_transactions && self.witness_commitment_hex.is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnderClaimedReward {
+    pub height: u64,
+    pub blockhash: String,
+    pub claimed_reward: u64,
+    pub allowed_reward: u64,
+    pub under_claimed_reward: u64,
+}
+
+struct DecodedCoinbaseScriptSig {
+    height: Option<i64>,
+    extranonce_hex: Option<String>,
+    tag: String,
+}
+
+fn decode_coinbase_script_sig(script_sig: &[u8]) -> DecodedCoinbaseScriptSig {
+    let height_instruction_length = match script_sig.first() {
+        Some(push_length @ 0x01..=0x08) => 1 + *push_length as usize,
+        Some(_) => 1,
+        None => 0,
+    };
+    let height = match script_sig.first() {
+        Some(0x01..=0x08) => script_sig
+            .get(1..height_instruction_length)
+            .and_then(decode_script_number),
+        Some(opcode) => decode_small_number_opcode(*opcode),
+        None => None,
+    };
+    let rest_of_script_sig = script_sig
+        .get(height_instruction_length..)
+        .unwrap_or_default();
+    // Miners don't have to push valid script after the height, so when the rest doesn't parse, its
+    // raw bytes are kept instead.
+    let extranonce_hex = match parse_script(rest_of_script_sig) {
+        Some(instructions) => match instructions.first() {
+            Some(ScriptInstruction::Push(data)) if !data.is_empty() => Some(encode_hex(data)),
+            _ => None,
+        },
+        None if !rest_of_script_sig.is_empty() => Some(encode_hex(rest_of_script_sig)),
+        None => None,
+    };
+    DecodedCoinbaseScriptSig {
+        height,
+        extranonce_hex,
+        tag: to_printable_ascii(rest_of_script_sig),
+    }
+}
+
+fn get_merkle_root(mut hashes: Vec<Vec<u8>>) -> Vec<u8> {
+    while hashes.len() > 1 {
+        if hashes.len() % 2 == 1 {
+            hashes.push(hashes.last().unwrap().clone());
+        }
+        hashes = hashes
+            .chunks(2)
+            .map(|pair| sha256d(&[pair[0].as_slice(), pair[1].as_slice()].concat()).to_vec())
+            .collect();
+    }
+    hashes.pop().unwrap()
+}
+
+// The commitment is the last output matching the commitment pattern (BIP141).
+fn get_witness_commitment(coinbase_transaction: &DecodeRawTransactionResponse) -> Option<Vec<u8>> {
+    coinbase_transaction
+        .vout
+        .iter()
+        .rev()
+        .map(|vout| decode_hex(&vout.script_pub_key.hex))
+        .find(|script| script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_SCRIPT_PREFIX))
+        .map(|script| script[6..38].to_vec())
+}
+
+// commitment = SHA256d(witness merkle root || witness reserved value), where the coinbase's wtxid
+// counts as all zeros and the reserved value is the coinbase input's witness.
+fn is_witness_commitment_valid(
+    witness_commitment: &[u8],
+    transactions: &[DecodeRawTransactionResponse],
+) -> bool {
+    let coinbase_witness_reserved_value = match transactions.first().unwrap().vin.first() {
+        Some(Vin::Coinbase(coinbase_vin)) => coinbase_vin
+            .txinwitness
+            .as_ref()
+            .and_then(|witness| witness.first())
+            .map(|witness_item| decode_hex(&witness_item.0)),
+        _ => None,
+    };
+    let witness_reserved_value = match coinbase_witness_reserved_value {
+        Some(witness_reserved_value) => witness_reserved_value,
+        None => return false,
+    };
+    let wtxids: Vec<Vec<u8>> = transactions
+        .iter()
+        .enumerate()
+        .map(|(i, transaction)| {
+            if i == 0 {
+                vec![0; 32]
+            } else {
+                decode_reversed_hex(&transaction.hash)
+            }
+        })
+        .collect();
+    let witness_merkle_root = get_merkle_root(wtxids);
+    let expected_witness_commitment =
+        sha256d(&[witness_merkle_root, witness_reserved_value].concat());
+    expected_witness_commitment.as_slice() == witness_commitment
+}
+
+pub(crate) fn get_subsidy_and_fees_for_block_at_height(client: &Client, height: u64) -> (u64, u64) {
+    let client = &client.bitcoind_request_client;
+    let block_stats = GetBlockStatsCommand::new(TargetBlockArgument::Height(height))
+        .add_selective_stats(vec![
+            StatsArgumentChoices::Subsidy,
+            StatsArgumentChoices::TotalFee,
+        ])
+        .call(client);
+    match block_stats.unwrap() {
+        GetBlockStatsCommandResponse::AllStats(response) => (response.subsidy, response.totalfee),
+        GetBlockStatsCommandResponse::SelectiveStats(response) => {
+            (response.subsidy.unwrap(), response.totalfee.unwrap())
+        }
+    }
+}
+
+pub(crate) fn get_claimed_reward(coinbase_transaction: &DecodeRawTransactionResponse) -> u64 {
+    coinbase_transaction
+        .vout
+        .iter()
+        .map(|vout| btc_to_sats(vout.value))
+        .sum()
+}
+
+pub(crate) fn get_coinbase_audit(
+    network: Network,
+    height: u64,
+    blockhash: String,
+    transactions: &[DecodeRawTransactionResponse],
+    subsidy: u64,
+    fees: u64,
+) -> CoinbaseAudit {
+    let coinbase_transaction = transactions.first().unwrap();
+    let script_sig_hex = get_coinbase_script_sig_hex(coinbase_transaction).to_string();
+    let decoded_script_sig = decode_coinbase_script_sig(&decode_hex(&script_sig_hex));
+    let bip34_height_is_valid = if height >= network.bip34_activation_height() {
+        Some(decoded_script_sig.height == Some(height as i64))
+    } else {
+        None
+    };
+    let has_witness_transactions = transactions
+        .iter()
+        .skip(1)
+        .any(|transaction| transaction.hash != transaction.txid);
+    let witness_commitment = get_witness_commitment(coinbase_transaction);
+    let witness_commitment_is_valid = witness_commitment
+        .as_ref()
+        .map(|witness_commitment| is_witness_commitment_valid(witness_commitment, transactions));
+    let payout_outputs_count = coinbase_transaction
+        .vout
+        .iter()
+        .filter(|vout| vout.script_pub_key.type_ != "nulldata")
+        .count() as u64;
+    CoinbaseAudit {
+        height,
+        blockhash,
+        txid: coinbase_transaction.txid.clone(),
+        script_sig_hex,
+        bip34_height: decoded_script_sig.height,
+        bip34_height_is_valid,
+        extranonce_hex: decoded_script_sig.extranonce_hex,
+        tag: decoded_script_sig.tag,
+        has_witness_transactions,
+        witness_commitment_hex: witness_commitment.map(|commitment| encode_hex(&commitment)),
+        witness_commitment_is_valid,
+        payout_outputs_count,
+        claimed_reward: get_claimed_reward(coinbase_transaction),
+        subsidy,
+        fees,
+    }
+}
+
+fn get_coinbase_audit_for_block_at_height_on_network(
+    client: &Client,
+    network: Network,
+    height: u64,
+) -> CoinbaseAudit {
+    let block = get_block_with_transactions_at_height(client, height);
+    let transactions = get_transactions_of_block(block.tx);
+    let (subsidy, fees) = get_subsidy_and_fees_for_block_at_height(client, height);
+    get_coinbase_audit(network, height, block.hash, &transactions, subsidy, fees)
+}
+
+pub fn get_coinbase_audit_for_block_at_height(client: &Client, height: u64) -> CoinbaseAudit {
+    get_coinbase_audit_for_block_at_height_on_network(client, get_network(client), height)
+}
+
+// takes a long time
+pub fn get_coinbase_audits_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> Vec<CoinbaseAudit> {
+    let network = get_network(client);
+    heights
+        .map(|height| get_coinbase_audit_for_block_at_height_on_network(client, network, height))
+        .collect()
+}
+
+// Only fetches the coinbase transaction of the block instead of the whole block, by passing the
+// blockhash to getrawtransaction (which doesn't need txindex).
+fn get_coinbase_transaction_of_block_at_height(
+    client: &Client,
+    height: u64,
+) -> (String, DecodeRawTransactionResponse) {
+    let bitcoind_request_client = &client.bitcoind_request_client;
+    let blockhash = GetBlockHashCommand::new(height)
+        .call(bitcoind_request_client)
+        .unwrap()
+        .0;
+    let get_block_response = GetBlockCommand::new(blockhash)
+        .verbosity(GetBlockCommandVerbosity::BlockObjectWithoutTransactionInformation)
+        .call(bitcoind_request_client);
+    let block = match get_block_response.unwrap() {
+        GetBlockCommandResponse::Block(block) => block,
+        GetBlockCommandResponse::BlockHash(_) => unreachable!(),
+    };
+    let coinbase_txid = match block.tx.first().unwrap() {
+        GetBlockCommandTransactionResponse::Id(txid) => txid.clone(),
+        GetBlockCommandTransactionResponse::Raw(transaction) => transaction.txid.clone(),
+    };
+    let coinbase_transaction: DecodeRawTransactionResponse = client
+        .call(
+            "getrawtransaction",
+            &[
+                to_raw_value(&coinbase_txid).unwrap(),
+                to_raw_value(&true).unwrap(),
+                to_raw_value(&block.hash).unwrap(),
+            ],
+        )
+        .unwrap();
+    (block.hash, coinbase_transaction)
+}
+
+pub fn get_under_claimed_reward_for_block_at_height(
+    client: &Client,
+    height: u64,
+) -> UnderClaimedReward {
+    // The genesis coinbase can't be fetched through getrawtransaction. It claimed exactly the 50
+    // btc subsidy (which is unspendable, but not under-claimed).
+    if height == 0 {
+        let bitcoind_request_client = &client.bitcoind_request_client;
+        let blockhash = GetBlockHashCommand::new(height)
+            .call(bitcoind_request_client)
+            .unwrap()
+            .0;
+        let (subsidy, fees) = get_subsidy_and_fees_for_block_at_height(client, height);
+        return UnderClaimedReward {
+            height,
+            blockhash: blockhash.0,
+            claimed_reward: subsidy + fees,
+            allowed_reward: subsidy + fees,
+            under_claimed_reward: 0,
+        };
+    }
+    let (blockhash, coinbase_transaction) =
+        get_coinbase_transaction_of_block_at_height(client, height);
+    let (subsidy, fees) = get_subsidy_and_fees_for_block_at_height(client, height);
+    let claimed_reward = get_claimed_reward(&coinbase_transaction);
+    let allowed_reward = subsidy + fees;
+    UnderClaimedReward {
+        height,
+        blockhash,
+        claimed_reward,
+        allowed_reward,
+        under_claimed_reward: allowed_reward.saturating_sub(claimed_reward),
+    }
+}
+
+// Only returns the blocks that under-claimed their reward.
+// takes a long time
+pub fn get_under_claimed_rewards_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> Vec<UnderClaimedReward> {
+    heights
+        .map(|height| get_under_claimed_reward_for_block_at_height(client, height))
+        .filter(|under_claimed_reward| under_claimed_reward.under_claimed_reward > 0)
+        .collect()
+}
+
+// Total sats permanently lost by miners under-claiming their reward, over the whole chain.
+// takes a very long time
+pub fn get_total_under_claimed_reward(client: &Client) -> u64 {
+    let block_count = get_block_height(client);
+    get_under_claimed_rewards_for_block_range(client, 0..=block_count)
+        .iter()
+        .map(|under_claimed_reward| under_claimed_reward.under_claimed_reward)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoind_request::command::get_block::{
+        CoinbaseVin, HexEncodedWitnessData, ScriptPubKey, Vout,
+    };
+
+    fn coinbase_transaction(
+        witness_commitment_script_hex: &str,
+        witness_reserved_value_hex: &str,
+    ) -> DecodeRawTransactionResponse {
+        DecodeRawTransactionResponse {
+            in_active_chain: None,
+            hex: String::new(),
+            txid: String::new(),
+            hash: String::new(),
+            size: 0,
+            vsize: 0,
+            weight: 0,
+            version: 1,
+            locktime: 0,
+            vin: vec![Vin::Coinbase(CoinbaseVin {
+                coinbase: String::new(),
+                sequence: 0xffffffff,
+                txinwitness: Some(vec![HexEncodedWitnessData(
+                    witness_reserved_value_hex.to_string(),
+                )]),
+            })],
+            vout: vec![Vout {
+                value: 0.0,
+                n: 0,
+                script_pub_key: ScriptPubKey {
+                    asm: String::new(),
+                    hex: witness_commitment_script_hex.to_string(),
+                    address: None,
+                    type_: "nulldata".to_string(),
+                },
+            }],
+        }
+    }
+
+    // Every block with only the coinbase commits to the same value, which is the one found in the
+    // coinbase of the empty blocks mined since segwit activated.
+    #[test]
+    fn witness_commitment_of_empty_block_is_valid() {
+        let witness_commitment_script_hex =
+            "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9";
+        let witness_reserved_value_hex =
+            "0000000000000000000000000000000000000000000000000000000000000000";
+        let transactions = vec![coinbase_transaction(
+            witness_commitment_script_hex,
+            witness_reserved_value_hex,
+        )];
+        let witness_commitment = get_witness_commitment(&transactions[0]).unwrap();
+        assert_eq!(
+            encode_hex(&witness_commitment),
+            "e2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9"
+        );
+        assert!(is_witness_commitment_valid(
+            &witness_commitment,
+            &transactions
+        ));
+    }
+
+    #[test]
+    fn witness_commitment_with_another_reserved_value_is_invalid() {
+        let transactions = vec![coinbase_transaction(
+            "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9",
+            "0100000000000000000000000000000000000000000000000000000000000000",
+        )];
+        let witness_commitment = get_witness_commitment(&transactions[0]).unwrap();
+        assert!(!is_witness_commitment_valid(
+            &witness_commitment,
+            &transactions
+        ));
+    }
+
+    #[test]
+    fn extranonce_is_the_push_after_the_height() {
+        // height 200_000, then a 4 bytes push
+        let decoded_script_sig = decode_coinbase_script_sig(&decode_hex("03400d0304deadbeef"));
+        assert_eq!(decoded_script_sig.height, Some(200_000));
+        assert_eq!(
+            decoded_script_sig.extranonce_hex.as_deref(),
+            Some("deadbeef")
+        );
+    }
+
+    #[test]
+    fn extranonce_keeps_the_bytes_after_the_height_when_they_dont_parse() {
+        // OP_PUSHDATA1 without its length byte
+        let decoded_script_sig = decode_coinbase_script_sig(&decode_hex("03400d034c"));
+        assert_eq!(decoded_script_sig.height, Some(200_000));
+        assert_eq!(decoded_script_sig.extranonce_hex.as_deref(), Some("4c"));
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::get_block::Vin;
+use jsonrpc::serde_json;
+
+use crate::fee_rates::FeeRateInterval;
+use crate::op_return::{get_op_return_payload, OpReturnProtocolDefinitions};
+use crate::prevouts::{BlockWithPrevouts, PrevoutResolver, DEFAULT_PREVOUT_CACHE_CAPACITY};
+use crate::script::{decode_hex, parse_script, ScriptInstruction, OP_1};
+use crate::script_types::{get_script_type, ScriptType};
+use crate::taproot::get_taproot_leaf_script;
+use crate::{btc_to_sats, get_block_heights_over_last_24_hours, Client};
+
+const OP_IF: u8 = 0x63;
+const OP_ENDIF: u8 = 0x68;
+const INSCRIPTION_PROTOCOL_ID: &[u8] = b"ord";
+const INSCRIPTION_CONTENT_TYPE_TAG: u8 = 1;
+// larger OP_RETURN outputs weren't relayed by default before bitcoind 30.0
+pub const MAX_STANDARD_OP_RETURN_SIZE: usize = 83;
+// larger leaf scripts are pushing data around, as no push can be larger than this
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+// When a transaction embeds data in several ways, it's counted once, under the first of these.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DataEmbeddingKind {
+    // inscriptions of BRC-20 operations
+    Brc20,
+    // ordinals inscriptions
+    Inscription,
+    // OP_RETURN outputs identified by the OpReturnProtocolDefinitions, like runestones, by
+    // protocol name
+    OpReturnProtocol(String),
+    // OP_RETURN outputs of no known protocol larger than MAX_STANDARD_OP_RETURN_SIZE, and
+    // tapscripts larger than MAX_SCRIPT_ELEMENT_SIZE that aren't inscriptions
+    LargeDataCarrier,
+    // any other OP_RETURN output
+    OpReturn,
+}
+
+impl DataEmbeddingKind {
+    pub fn name(&self) -> &str {
+        match self {
+            DataEmbeddingKind::Brc20 => "BRC-20",
+            DataEmbeddingKind::Inscription => "inscription",
+            DataEmbeddingKind::OpReturnProtocol(protocol_name) => protocol_name,
+            DataEmbeddingKind::LargeDataCarrier => "large data carrier",
+            DataEmbeddingKind::OpReturn => "OP_RETURN",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inscription {
+    // mime type, like "text/plain;charset=utf-8" or "image/png"
+    pub content_type: Option<String>,
+    // in bytes
+    pub body_size: u64,
+    pub is_brc20: bool,
+}
+
+// All fees in sats.
+#[derive(Debug, Clone)]
+pub struct DataEmbeddingUsage {
+    pub kind: DataEmbeddingKind,
+    pub transactions_count: u64,
+    pub weight: u64,
+    pub fees: u64,
+    // between 0.0 and 100.0
+    pub percent_of_block_space: f64,
+    pub percent_of_fees: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockDataEmbedding {
+    pub height: u64,
+    pub time: u64,
+    // includes the coinbase
+    pub weight: u64,
+    // in sats
+    pub fees: u64,
+    // ordered by kind, kinds without transactions left out
+    pub usages: Vec<DataEmbeddingUsage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DataEmbeddingWindow {
+    // unix timestamp of the start of the interval
+    pub start_time: u64,
+    pub blocks_count: u64,
+    pub weight: u64,
+    // in sats
+    pub fees: u64,
+    pub usages: Vec<DataEmbeddingUsage>,
+}
+
+fn is_empty_push(instruction: &ScriptInstruction) -> bool {
+    *instruction == ScriptInstruction::Push(vec![])
+}
+
+// BRC-20 operations are JSON objects with "p" set to "brc-20".
+fn is_brc20_body(body: &[u8]) -> bool {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(json) => json
+            .get("p")
+            .and_then(|protocol| protocol.as_str())
+            .map(|protocol| protocol.eq_ignore_ascii_case("brc-20"))
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+// Inscription envelopes of a leaf script: OP_FALSE OP_IF "ord", tag and value pairs, then an
+// empty push and the body, up to OP_ENDIF.
+pub fn get_inscriptions(leaf_script: &[u8]) -> Vec<Inscription> {
+    let instructions = match parse_script(leaf_script) {
+        Some(instructions) => instructions,
+        None => return vec![],
+    };
+    let mut inscriptions = vec![];
+    let mut i = 0;
+    while i + 2 < instructions.len() {
+        let is_envelope_start = is_empty_push(&instructions[i])
+            && instructions[i + 1] == ScriptInstruction::Op(OP_IF)
+            && instructions[i + 2] == ScriptInstruction::Push(INSCRIPTION_PROTOCOL_ID.to_vec());
+        if !is_envelope_start {
+            i += 1;
+            continue;
+        }
+        i += 3;
+        let mut content_type = None;
+        let mut body: Vec<u8> = vec![];
+        let mut in_body = false;
+        while i < instructions.len() && instructions[i] != ScriptInstruction::Op(OP_ENDIF) {
+            match (&instructions[i], instructions.get(i + 1), in_body) {
+                (ScriptInstruction::Push(data), _, true) => body.extend(data),
+                (instruction, _, false) if is_empty_push(instruction) => in_body = true,
+                // the tag can be pushed as data, or as OP_1
+                (ScriptInstruction::Push(tag), Some(ScriptInstruction::Push(value)), false)
+                    if tag.as_slice() == [INSCRIPTION_CONTENT_TYPE_TAG] =>
+                {
+                    content_type = Some(String::from_utf8_lossy(value).to_string());
+                    i += 1;
+                }
+                (ScriptInstruction::Op(OP_1), Some(ScriptInstruction::Push(value)), false) => {
+                    content_type = Some(String::from_utf8_lossy(value).to_string());
+                    i += 1;
+                }
+                // other tags, like the pointer or the parent, with their value
+                (_, Some(_), false) => i += 1,
+                _ => {}
+            }
+            i += 1;
+        }
+        inscriptions.push(Inscription {
+            content_type,
+            body_size: body.len() as u64,
+            is_brc20: is_brc20_body(&body),
+        });
+    }
+    inscriptions
+}
+
+// None when the transaction doesn't embed data. `spent_script_types` and `witnesses` have one entry
+// per input, and only the witnesses of P2TR spends are looked at. OP_RETURN outputs are told apart
+// with the same definitions as get_op_return_report_for_block_range, so `first_input_txid` is only
+// needed for the encrypted protocols.
+pub fn get_data_embedding_kind(
+    definitions: &OpReturnProtocolDefinitions,
+    spent_script_types: &[ScriptType],
+    witnesses: &[Vec<Vec<u8>>],
+    output_scripts: &[Vec<u8>],
+    first_input_txid: Option<&str>,
+) -> Option<DataEmbeddingKind> {
+    let mut kinds = vec![];
+    for (spent_script_type, witness) in spent_script_types.iter().zip(witnesses) {
+        if *spent_script_type != ScriptType::P2tr {
+            continue;
+        }
+        if let Some(leaf_script) = get_taproot_leaf_script(witness) {
+            let inscriptions = get_inscriptions(leaf_script);
+            if inscriptions.iter().any(|inscription| inscription.is_brc20) {
+                kinds.push(DataEmbeddingKind::Brc20);
+            } else if !inscriptions.is_empty() {
+                kinds.push(DataEmbeddingKind::Inscription);
+            } else if leaf_script.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                kinds.push(DataEmbeddingKind::LargeDataCarrier);
+            }
+        }
+    }
+    for script in output_scripts {
+        if get_op_return_payload(script).is_none() {
+            continue;
+        }
+        kinds.push(match definitions.identify(script, first_input_txid) {
+            Some(protocol) => DataEmbeddingKind::OpReturnProtocol(protocol.name.clone()),
+            None if script.len() > MAX_STANDARD_OP_RETURN_SIZE => {
+                DataEmbeddingKind::LargeDataCarrier
+            }
+            None => DataEmbeddingKind::OpReturn,
+        });
+    }
+    kinds.into_iter().min()
+}
+
+// transactions count, weight and fees by kind
+type DataEmbeddingTotals = BTreeMap<DataEmbeddingKind, (u64, u64, u64)>;
+
+fn get_data_embedding_usages(
+    totals: &DataEmbeddingTotals,
+    weight: u64,
+    fees: u64,
+) -> Vec<DataEmbeddingUsage> {
+    totals
+        .iter()
+        .map(
+            |(kind, (transactions_count, kind_weight, kind_fees))| DataEmbeddingUsage {
+                kind: kind.clone(),
+                transactions_count: *transactions_count,
+                weight: *kind_weight,
+                fees: *kind_fees,
+                percent_of_block_space: *kind_weight as f64 / weight as f64 * 100.0,
+                percent_of_fees: if fees == 0 {
+                    0.0
+                } else {
+                    *kind_fees as f64 / fees as f64 * 100.0
+                },
+            },
+        )
+        .collect()
+}
+
+fn get_data_embedding_of_block(
+    block: &BlockWithPrevouts,
+    definitions: &OpReturnProtocolDefinitions,
+) -> BlockDataEmbedding {
+    let mut totals: DataEmbeddingTotals = BTreeMap::new();
+    let mut fees = 0;
+    for (transaction, prevouts) in block.transactions.iter().zip(block.get_prevouts()).skip(1) {
+        let fee = prevouts.iter().map(|prevout| prevout.value).sum::<u64>()
+            - transaction
+                .vout
+                .iter()
+                .map(|vout| btc_to_sats(vout.value))
+                .sum::<u64>();
+        fees += fee;
+        let spent_script_types: Vec<ScriptType> = prevouts
+            .iter()
+            .map(|prevout| get_script_type(&decode_hex(&prevout.script_pub_key_hex)))
+            .collect();
+        let witnesses: Vec<Vec<Vec<u8>>> = transaction
+            .vin
+            .iter()
+            .map(|vin| match vin {
+                Vin::NonCoinbase(vin) => vin
+                    .txinwitness
+                    .iter()
+                    .flatten()
+                    .map(|stack_element| decode_hex(&stack_element.0))
+                    .collect(),
+                Vin::Coinbase(_) => vec![],
+            })
+            .collect();
+        let output_scripts: Vec<Vec<u8>> = transaction
+            .vout
+            .iter()
+            .map(|vout| decode_hex(&vout.script_pub_key.hex))
+            .collect();
+        let first_input_txid = match transaction.vin.first() {
+            Some(Vin::NonCoinbase(vin)) => Some(vin.txid.as_str()),
+            _ => None,
+        };
+        if let Some(kind) = get_data_embedding_kind(
+            definitions,
+            &spent_script_types,
+            &witnesses,
+            &output_scripts,
+            first_input_txid,
+        ) {
+            let kind_totals = totals.entry(kind).or_default();
+            kind_totals.0 += 1;
+            kind_totals.1 += transaction.weight;
+            kind_totals.2 += fee;
+        }
+    }
+    BlockDataEmbedding {
+        height: block.height,
+        time: block.time,
+        weight: block.weight,
+        fees,
+        usages: get_data_embedding_usages(&totals, block.weight, fees),
+    }
+}
+
+// Spent outputs are resolved with the PrevoutResolver, to only look for inscriptions in P2TR
+// spends, and it panics when they can't be.
+pub fn get_data_embedding_for_block_at_height(
+    client: &Client,
+    height: u64,
+    definitions: &OpReturnProtocolDefinitions,
+) -> BlockDataEmbedding {
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    get_data_embedding_of_block(
+        &prevout_resolver.get_block_with_prevouts_at_height(client, height),
+        definitions,
+    )
+}
+
+// takes a long time
+pub fn get_data_embedding_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    definitions: &OpReturnProtocolDefinitions,
+) -> Vec<BlockDataEmbedding> {
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    heights
+        .map(|height| {
+            get_data_embedding_of_block(
+                &prevout_resolver.get_block_with_prevouts_at_height(client, height),
+                definitions,
+            )
+        })
+        .collect()
+}
+
+// takes a long time
+pub fn get_data_embedding_over_last_24_hours(
+    client: &Client,
+    definitions: &OpReturnProtocolDefinitions,
+) -> Vec<BlockDataEmbedding> {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_data_embedding_for_block_range(client, heights, definitions)
+}
+
+// Groups the blocks by their timestamp. Ordered by time.
+pub fn get_data_embedding_windows(
+    blocks: &[BlockDataEmbedding],
+    interval: FeeRateInterval,
+) -> Vec<DataEmbeddingWindow> {
+    let mut windows: BTreeMap<u64, (u64, u64, u64, DataEmbeddingTotals)> = BTreeMap::new();
+    for block in blocks {
+        let start_time = block.time - block.time % interval.seconds();
+        let (blocks_count, weight, fees, totals) = windows.entry(start_time).or_default();
+        *blocks_count += 1;
+        *weight += block.weight;
+        *fees += block.fees;
+        for usage in &block.usages {
+            let kind_totals = totals.entry(usage.kind.clone()).or_default();
+            kind_totals.0 += usage.transactions_count;
+            kind_totals.1 += usage.weight;
+            kind_totals.2 += usage.fees;
+        }
+    }
+    windows
+        .into_iter()
+        .map(
+            |(start_time, (blocks_count, weight, fees, totals))| DataEmbeddingWindow {
+                start_time,
+                blocks_count,
+                weight,
+                fees,
+                usages: get_data_embedding_usages(&totals, weight, fees),
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{OP_0, OP_PUSHDATA2};
+
+    const OP_CHECKSIG: u8 = 0xac;
+    const BRC20_MINT: &[u8] = br#"{"p":"brc-20","op":"mint","tick":"ordi","amt":"1000"}"#;
+
+    fn push(data: &[u8]) -> Vec<u8> {
+        let mut script = match data.len() {
+            0 => vec![OP_0],
+            length if length <= 75 => vec![length as u8],
+            length => {
+                let mut script = vec![OP_PUSHDATA2];
+                script.extend((length as u16).to_le_bytes());
+                script
+            }
+        };
+        script.extend(data);
+        script
+    }
+
+    // <key> OP_CHECKSIG followed by an envelope with the tag and value pairs, then the body
+    // pushes
+    fn leaf_script(fields: &[Vec<u8>], body_pushes: &[&[u8]]) -> Vec<u8> {
+        let mut script = push(&[0xab; 32]);
+        script.push(OP_CHECKSIG);
+        script.extend([OP_0, OP_IF]);
+        script.extend(push(INSCRIPTION_PROTOCOL_ID));
+        for field in fields {
+            script.extend(field);
+        }
+        script.push(OP_0);
+        for body_push in body_pushes {
+            script.extend(push(body_push));
+        }
+        script.push(OP_ENDIF);
+        script
+    }
+
+    fn content_type_field(content_type: &str) -> Vec<u8> {
+        let mut field = push(&[INSCRIPTION_CONTENT_TYPE_TAG]);
+        field.extend(push(content_type.as_bytes()));
+        field
+    }
+
+    #[test]
+    fn text_inscription() {
+        let script = leaf_script(
+            &[content_type_field("text/plain;charset=utf-8")],
+            &[b"Hello, world!"],
+        );
+        assert_eq!(
+            get_inscriptions(&script),
+            vec![Inscription {
+                content_type: Some("text/plain;charset=utf-8".to_string()),
+                body_size: 13,
+                is_brc20: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn body_spans_several_pushes_and_other_tags_are_skipped() {
+        let body = [0x89; 600];
+        // pointer tag (2) with its value, then the content type tag pushed as OP_1
+        let mut pointer_field = push(&[2]);
+        pointer_field.extend(push(&[0x01]));
+        let mut content_type_field = vec![OP_1];
+        content_type_field.extend(push(b"image/png"));
+        let script = leaf_script(
+            &[pointer_field, content_type_field],
+            &[
+                &body[..MAX_SCRIPT_ELEMENT_SIZE],
+                &body[MAX_SCRIPT_ELEMENT_SIZE..],
+            ],
+        );
+        assert_eq!(
+            get_inscriptions(&script),
+            vec![Inscription {
+                content_type: Some("image/png".to_string()),
+                body_size: 600,
+                is_brc20: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn inscriptions_without_content_type_or_body() {
+        let script = leaf_script(&[], &[]);
+        assert_eq!(
+            get_inscriptions(&script),
+            vec![Inscription {
+                content_type: None,
+                body_size: 0,
+                is_brc20: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn every_envelope_of_the_script_is_an_inscription() {
+        let mut script = leaf_script(&[content_type_field("text/plain")], &[b"a"]);
+        // the second envelope, without the key and OP_CHECKSIG
+        script.extend(&leaf_script(&[content_type_field("text/plain")], &[b"bc"])[34..]);
+        let body_sizes: Vec<u64> = get_inscriptions(&script)
+            .iter()
+            .map(|inscription| inscription.body_size)
+            .collect();
+        assert_eq!(body_sizes, vec![1, 2]);
+    }
+
+    #[test]
+    fn scripts_without_envelope_have_no_inscriptions() {
+        let mut script = push(&[0xab; 32]);
+        script.push(OP_CHECKSIG);
+        assert_eq!(get_inscriptions(&script), vec![]);
+        // OP_FALSE OP_IF with another protocol id
+        script.extend([OP_0, OP_IF]);
+        script.extend(push(b"xyz"));
+        script.push(OP_ENDIF);
+        assert_eq!(get_inscriptions(&script), vec![]);
+        // a push running past the end of the script
+        assert_eq!(get_inscriptions(&[0x20, 0xab]), vec![]);
+    }
+
+    #[test]
+    fn brc20_operations_are_json_with_the_brc20_protocol() {
+        let script = leaf_script(&[content_type_field("text/plain")], &[BRC20_MINT]);
+        assert!(get_inscriptions(&script)[0].is_brc20);
+
+        assert!(is_brc20_body(BRC20_MINT));
+        assert!(is_brc20_body(br#"{"p":"BRC-20","op":"transfer"}"#));
+        assert!(!is_brc20_body(br#"{"p":"sns","op":"reg"}"#));
+        assert!(!is_brc20_body(br#"{"p":20}"#));
+        assert!(!is_brc20_body(br#"["brc-20"]"#));
+        assert!(!is_brc20_body(b"brc-20"));
+    }
+
+    #[test]
+    fn data_embedding_kind_prefers_brc20_over_op_return() {
+        let definitions = OpReturnProtocolDefinitions::default();
+        let script = leaf_script(&[content_type_field("text/plain")], &[BRC20_MINT]);
+        let mut control_block = vec![0xc0];
+        control_block.extend([0xcd; 32]);
+        let witness = vec![vec![0x01; 64], script, control_block];
+        let op_return_script = decode_hex("6a0401020304");
+        assert_eq!(
+            get_data_embedding_kind(
+                &definitions,
+                &[ScriptType::P2tr],
+                std::slice::from_ref(&witness),
+                std::slice::from_ref(&op_return_script),
+                None,
+            ),
+            Some(DataEmbeddingKind::Brc20)
+        );
+        // only P2TR spends are looked at
+        assert_eq!(
+            get_data_embedding_kind(
+                &definitions,
+                &[ScriptType::P2wsh],
+                &[witness],
+                &[op_return_script],
+                None,
+            ),
+            Some(DataEmbeddingKind::OpReturn)
+        );
+        assert_eq!(
+            get_data_embedding_kind(&definitions, &[], &[], &[], None),
+            None
+        );
+    }
+}
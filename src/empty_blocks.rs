@@ -0,0 +1,174 @@
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::{
+    get_block_stats::{
+        GetBlockStatsCommand, GetBlockStatsCommandResponse, StatsArgumentChoices,
+        TargetBlockArgument,
+    },
+    CallableCommand,
+};
+
+use crate::mining_pool::get_pool_for_block_at_height;
+use crate::{get_timestamp_of_block_at_height, Client, PoolDefinitions};
+
+// A block is flagged when it has at most `max_transactions_count` transactions (coinbase included)
+// or, if set, when its non-coinbase transactions weigh at most `max_weight`.
+#[derive(Debug, Clone)]
+pub struct EmptyBlockThreshold {
+    pub max_transactions_count: u64,
+    pub max_weight: Option<u64>,
+}
+
+impl Default for EmptyBlockThreshold {
+    // Only blocks containing nothing but the coinbase transaction.
+    fn default() -> Self {
+        EmptyBlockThreshold {
+            max_transactions_count: 1,
+            max_weight: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmptyBlock {
+    pub height: u64,
+    pub blockhash: String,
+    pub time: u64,
+    // including the coinbase transaction
+    pub transactions_count: u64,
+    // weight of all non-coinbase transactions
+    pub weight: u64,
+    // can be negative, as block timestamps are not strictly increasing
+    pub seconds_since_previous_block: i64,
+    pub pool_name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmptyBlocksReport {
+    pub blocks_count: u64,
+    pub empty_blocks: Vec<EmptyBlock>,
+    // None when there are no blocks to average
+    pub average_seconds_since_previous_block: Option<f64>,
+    pub average_seconds_since_previous_block_for_empty_blocks: Option<f64>,
+}
+
+struct BlockSummary {
+    blockhash: String,
+    time: u64,
+    transactions_count: u64,
+    weight: u64,
+}
+
+fn get_block_summary_at_height(client: &Client, height: u64) -> BlockSummary {
+    let client = &client.bitcoind_request_client;
+    let block_stats = GetBlockStatsCommand::new(TargetBlockArgument::Height(height))
+        .add_selective_stats(vec![
+            StatsArgumentChoices::Blockhash,
+            StatsArgumentChoices::Time,
+            StatsArgumentChoices::Txs,
+            StatsArgumentChoices::TotalWeight,
+        ])
+        .call(client);
+    match block_stats.unwrap() {
+        GetBlockStatsCommandResponse::AllStats(response) => BlockSummary {
+            blockhash: response.blockhash,
+            time: response.time,
+            transactions_count: response.txs,
+            weight: response.total_weight,
+        },
+        GetBlockStatsCommandResponse::SelectiveStats(response) => BlockSummary {
+            blockhash: response.blockhash.unwrap(),
+            time: response.time.unwrap(),
+            transactions_count: response.txs.unwrap(),
+            weight: response.total_weight.unwrap(),
+        },
+    }
+}
+
+impl EmptyBlockThreshold {
+    fn is_met_by(&self, block_summary: &BlockSummary) -> bool {
+        let is_below_transactions_count =
+            block_summary.transactions_count <= self.max_transactions_count;
+        let is_below_weight = match self.max_weight {
+            Some(max_weight) => block_summary.weight <= max_weight,
+            None => false,
+        };
+        is_below_transactions_count || is_below_weight
+    }
+}
+
+fn get_average(total: i64, count: u64) -> Option<f64> {
+    if count == 0 {
+        None
+    } else {
+        Some(total as f64 / count as f64)
+    }
+}
+
+// Pools are only looked up (which requires fetching the whole block) for the flagged blocks, and
+// only when pool definitions are passed.
+// takes a long time
+pub fn get_empty_blocks_report_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    threshold: &EmptyBlockThreshold,
+    definitions: Option<&PoolDefinitions>,
+) -> EmptyBlocksReport {
+    let start_height = *heights.start();
+    let mut previous_block_time = if start_height > 0 {
+        get_timestamp_of_block_at_height(client, start_height - 1) as i64
+    } else {
+        get_timestamp_of_block_at_height(client, start_height) as i64
+    };
+    let mut blocks_count = 0;
+    let mut total_seconds_since_previous_block = 0;
+    let mut empty_blocks = vec![];
+    for height in heights {
+        let block_summary = get_block_summary_at_height(client, height);
+        let seconds_since_previous_block = block_summary.time as i64 - previous_block_time;
+        previous_block_time = block_summary.time as i64;
+        blocks_count += 1;
+        total_seconds_since_previous_block += seconds_since_previous_block;
+
+        if threshold.is_met_by(&block_summary) {
+            let pool_name = definitions.and_then(|definitions| {
+                get_pool_for_block_at_height(client, definitions, height).pool_name
+            });
+            empty_blocks.push(EmptyBlock {
+                height,
+                blockhash: block_summary.blockhash,
+                time: block_summary.time,
+                transactions_count: block_summary.transactions_count,
+                weight: block_summary.weight,
+                seconds_since_previous_block,
+                pool_name,
+            });
+        }
+    }
+    let total_seconds_since_previous_block_for_empty_blocks: i64 = empty_blocks
+        .iter()
+        .map(|empty_block| empty_block.seconds_since_previous_block)
+        .sum();
+    EmptyBlocksReport {
+        blocks_count,
+        average_seconds_since_previous_block: get_average(
+            total_seconds_since_previous_block,
+            blocks_count,
+        ),
+        average_seconds_since_previous_block_for_empty_blocks: get_average(
+            total_seconds_since_previous_block_for_empty_blocks,
+            empty_blocks.len() as u64,
+        ),
+        empty_blocks,
+    }
+}
+
+// takes a long time
+pub fn get_empty_blocks_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    threshold: &EmptyBlockThreshold,
+    definitions: Option<&PoolDefinitions>,
+) -> Vec<EmptyBlock> {
+    get_empty_blocks_report_for_block_range(client, heights, threshold, definitions).empty_blocks
+}
@@ -0,0 +1,353 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::{get_block_hash::GetBlockHashCommand, CallableCommand};
+use jsonrpc::serde_json::value::to_raw_value;
+use serde::Deserialize;
+
+use crate::fee_rates::FeeRateInterval;
+use crate::mempool::{get_mempool_entries, MempoolEntry};
+use crate::mempool_events::MempoolEvent;
+use crate::{btc_to_sats, Client};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplacementKind {
+    // the replaced transaction, or one of its unconfirmed ancestors, opted in to replacement
+    Bip125Signaled,
+    // the replaced transaction didn't signal, and was replaced anyway
+    FullRbf,
+}
+
+// All fees in sats and fee rates in sat/vB.
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub replaced_txid: String,
+    pub replacing_txid: String,
+    // local time the replacement was seen
+    pub time: i64,
+    pub kind: ReplacementKind,
+    pub replaced_fee: u64,
+    pub replaced_fee_rate: f64,
+    // None when the replacing transaction was never seen in the mempool, like a conflicting
+    // transaction that got mined directly
+    pub replacing_fee: Option<u64>,
+    pub replacing_fee_rate: Option<f64>,
+    // replacing_fee - replaced_fee
+    pub fee_bump: Option<i64>,
+}
+
+// A transaction paying a higher fee rate than its unconfirmed ancestors mined in the same block.
+// Fee rates in sat/vB.
+#[derive(Debug, Clone)]
+pub struct CpfpPackage {
+    pub child_txid: String,
+    // ancestors of the child mined in the same block
+    pub parent_txids: Vec<String>,
+    pub child_fee_rate: f64,
+    // of all the parents combined
+    pub parents_fee_rate: f64,
+    pub package_fee_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockCpfpPackages {
+    pub height: u64,
+    pub time: u64,
+    // excludes the coinbase
+    pub transactions_count: u64,
+    // The node leaves out the fee when it lacks the undo data of the block (pruned nodes).
+    // Packages with one of these transactions are skipped.
+    pub transactions_without_fee_count: u64,
+    pub cpfp_packages: Vec<CpfpPackage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeBumpingWindow {
+    // unix timestamp of the start of the interval
+    pub start_time: u64,
+    pub replacements_count: u64,
+    pub bip125_replacements_count: u64,
+    pub full_rbf_replacements_count: u64,
+    // in sats, of the replacements whose replacing transaction was seen
+    pub average_fee_bump: f64,
+    pub blocks_count: u64,
+    pub transactions_count: u64,
+    pub cpfp_packages_count: u64,
+    // children and parents
+    pub cpfp_transactions_count: u64,
+    // between 0.0 and 100.0
+    pub percent_of_transactions_in_cpfp_packages: f64,
+}
+
+impl FeeBumpingWindow {
+    fn new(start_time: u64) -> Self {
+        FeeBumpingWindow {
+            start_time,
+            replacements_count: 0,
+            bip125_replacements_count: 0,
+            full_rbf_replacements_count: 0,
+            average_fee_bump: 0.0,
+            blocks_count: 0,
+            transactions_count: 0,
+            cpfp_packages_count: 0,
+            cpfp_transactions_count: 0,
+            percent_of_transactions_in_cpfp_packages: 0.0,
+        }
+    }
+}
+
+// Feed it the events of a MempoolEventStream to keep track of replacements.
+//
+//   let mut stream = MempoolEventStream::new(&client);
+//   let mut tracker = ReplacementTracker::new(&client);
+//   loop {
+//       let events = stream.poll(&client);
+//       for replacement in tracker.handle_events(&events) {
+//           println!("{:?}", replacement);
+//       }
+//       sleep(Duration::from_secs(1));
+//   }
+pub struct ReplacementTracker {
+    pub replacements: Vec<Replacement>,
+    entries_by_txid: HashMap<String, MempoolEntry>,
+}
+
+impl ReplacementTracker {
+    pub fn new(client: &Client) -> Self {
+        ReplacementTracker {
+            replacements: vec![],
+            entries_by_txid: get_mempool_entries(client),
+        }
+    }
+
+    // Expects all the events of one poll at once. Descendants removed along with a replaced
+    // transaction aren't counted as replacements themselves.
+    pub fn handle_events(&mut self, events: &[MempoolEvent]) -> Vec<Replacement> {
+        for event in events {
+            if let MempoolEvent::Added(entry) = event {
+                self.entries_by_txid
+                    .insert(entry.txid.clone(), entry.clone());
+            }
+        }
+        let replaced_txids: HashSet<&str> = events
+            .iter()
+            .filter_map(|event| match event {
+                MempoolEvent::Replaced { txid, .. } => Some(txid.as_str()),
+                _ => None,
+            })
+            .collect();
+        let now = chrono::offset::Utc::now().timestamp();
+        let mut new_replacements = vec![];
+        for event in events {
+            let (txid, replaced_by) = match event {
+                MempoolEvent::Added(_) => continue,
+                MempoolEvent::Replaced { txid, replaced_by } => (txid, replaced_by),
+                MempoolEvent::Confirmed { txid, .. } | MempoolEvent::Evicted { txid } => {
+                    self.entries_by_txid.remove(txid);
+                    continue;
+                }
+            };
+            // the transaction could have been added and replaced before we saw it
+            let replaced_entry = match self.entries_by_txid.remove(txid) {
+                Some(replaced_entry) => replaced_entry,
+                None => continue,
+            };
+            if replaced_entry
+                .depends
+                .iter()
+                .any(|parent_txid| replaced_txids.contains(parent_txid.as_str()))
+            {
+                continue;
+            }
+            let replacing_entry = self.entries_by_txid.get(replaced_by);
+            new_replacements.push(Replacement {
+                replaced_txid: txid.clone(),
+                replacing_txid: replaced_by.clone(),
+                time: now,
+                kind: if replaced_entry.bip125_replaceable {
+                    ReplacementKind::Bip125Signaled
+                } else {
+                    ReplacementKind::FullRbf
+                },
+                replaced_fee: replaced_entry.fee,
+                replaced_fee_rate: replaced_entry.fee_rate(),
+                replacing_fee: replacing_entry.map(|entry| entry.fee),
+                replacing_fee_rate: replacing_entry.map(|entry| entry.fee_rate()),
+                fee_bump: replacing_entry.map(|entry| entry.fee as i64 - replaced_entry.fee as i64),
+            });
+        }
+        self.replacements.extend(new_replacements.iter().cloned());
+        new_replacements
+    }
+}
+
+#[derive(Deserialize)]
+struct BlockTransactionVinResponse {
+    // missing for the coinbase
+    txid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BlockTransactionResponse {
+    txid: String,
+    vsize: u64,
+    // in btc, missing for the coinbase, and when the node lacks the undo data of the block
+    fee: Option<f64>,
+    vin: Vec<BlockTransactionVinResponse>,
+}
+
+// Not using GetBlockCommand, as its transactions don't include the fee.
+#[derive(Deserialize)]
+struct BlockResponse {
+    time: u64,
+    tx: Vec<BlockTransactionResponse>,
+}
+
+pub fn get_cpfp_packages_for_block_at_height(client: &Client, height: u64) -> BlockCpfpPackages {
+    let blockhash = GetBlockHashCommand::new(height)
+        .call(&client.bitcoind_request_client)
+        .unwrap()
+        .0;
+    let block: BlockResponse = client
+        .call(
+            "getblock",
+            &[
+                to_raw_value(&blockhash.0).unwrap(),
+                to_raw_value(&2).unwrap(),
+            ],
+        )
+        .unwrap();
+    let transactions: Vec<&BlockTransactionResponse> = block.tx.iter().skip(1).collect();
+    let transactions_by_txid: HashMap<&str, &BlockTransactionResponse> = transactions
+        .iter()
+        .map(|transaction| (transaction.txid.as_str(), *transaction))
+        .collect();
+    // parents always come before their children in a block
+    let mut ancestors_by_txid: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let mut cpfp_packages = vec![];
+    for transaction in &transactions {
+        let mut ancestors: HashSet<&str> = HashSet::new();
+        for vin in &transaction.vin {
+            if let Some(parent_txid) = vin.txid.as_deref() {
+                if let Some((parent_txid, _)) = transactions_by_txid.get_key_value(parent_txid) {
+                    ancestors.insert(parent_txid);
+                    ancestors.extend(ancestors_by_txid[parent_txid].iter().copied());
+                }
+            }
+        }
+        // None when the child or one of its ancestors has no fee
+        let child_fee = transaction.fee.map(btc_to_sats);
+        let parents_fee: Option<u64> = ancestors
+            .iter()
+            .map(|txid| transactions_by_txid[txid].fee.map(btc_to_sats))
+            .sum();
+        if let (false, Some(child_fee), Some(parents_fee)) =
+            (ancestors.is_empty(), child_fee, parents_fee)
+        {
+            let parents_vsize: u64 = ancestors
+                .iter()
+                .map(|txid| transactions_by_txid[txid].vsize)
+                .sum();
+            let child_fee_rate = child_fee as f64 / transaction.vsize as f64;
+            let parents_fee_rate = parents_fee as f64 / parents_vsize as f64;
+            if child_fee_rate > parents_fee_rate {
+                cpfp_packages.push(CpfpPackage {
+                    child_txid: transaction.txid.clone(),
+                    parent_txids: ancestors.iter().map(|txid| txid.to_string()).collect(),
+                    child_fee_rate,
+                    parents_fee_rate,
+                    package_fee_rate: (child_fee + parents_fee) as f64
+                        / (transaction.vsize + parents_vsize) as f64,
+                });
+            }
+        }
+        ancestors_by_txid.insert(transaction.txid.as_str(), ancestors);
+    }
+    BlockCpfpPackages {
+        height,
+        time: block.time,
+        transactions_count: transactions.len() as u64,
+        transactions_without_fee_count: transactions
+            .iter()
+            .filter(|transaction| transaction.fee.is_none())
+            .count() as u64,
+        cpfp_packages,
+    }
+}
+
+// takes a long time
+pub fn get_cpfp_packages_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> Vec<BlockCpfpPackages> {
+    heights
+        .map(|height| get_cpfp_packages_for_block_at_height(client, height))
+        .collect()
+}
+
+// Groups the replacements by the time they were seen and the blocks by their timestamp. Ordered by
+// time.
+pub fn get_fee_bumping_windows(
+    replacements: &[Replacement],
+    blocks_cpfp_packages: &[BlockCpfpPackages],
+    interval: FeeRateInterval,
+) -> Vec<FeeBumpingWindow> {
+    let mut windows: BTreeMap<u64, FeeBumpingWindow> = BTreeMap::new();
+    let mut fee_bumps_by_start_time: HashMap<u64, Vec<i64>> = HashMap::new();
+    for replacement in replacements {
+        let time = replacement.time as u64;
+        let start_time = time - time % interval.seconds();
+        let window = windows
+            .entry(start_time)
+            .or_insert_with(|| FeeBumpingWindow::new(start_time));
+        window.replacements_count += 1;
+        match replacement.kind {
+            ReplacementKind::Bip125Signaled => window.bip125_replacements_count += 1,
+            ReplacementKind::FullRbf => window.full_rbf_replacements_count += 1,
+        }
+        if let Some(fee_bump) = replacement.fee_bump {
+            fee_bumps_by_start_time
+                .entry(start_time)
+                .or_default()
+                .push(fee_bump);
+        }
+    }
+    for block_cpfp_packages in blocks_cpfp_packages {
+        let time = block_cpfp_packages.time;
+        let start_time = time - time % interval.seconds();
+        let window = windows
+            .entry(start_time)
+            .or_insert_with(|| FeeBumpingWindow::new(start_time));
+        window.blocks_count += 1;
+        window.transactions_count += block_cpfp_packages.transactions_count;
+        window.cpfp_packages_count += block_cpfp_packages.cpfp_packages.len() as u64;
+        // a parent can be shared by several packages
+        let cpfp_txids: HashSet<&str> = block_cpfp_packages
+            .cpfp_packages
+            .iter()
+            .flat_map(|cpfp_package| {
+                cpfp_package
+                    .parent_txids
+                    .iter()
+                    .chain(std::iter::once(&cpfp_package.child_txid))
+                    .map(|txid| txid.as_str())
+            })
+            .collect();
+        window.cpfp_transactions_count += cpfp_txids.len() as u64;
+    }
+    windows
+        .into_values()
+        .map(|mut window| {
+            if let Some(fee_bumps) = fee_bumps_by_start_time.get(&window.start_time) {
+                window.average_fee_bump =
+                    fee_bumps.iter().sum::<i64>() as f64 / fee_bumps.len() as f64;
+            }
+            if window.transactions_count > 0 {
+                window.percent_of_transactions_in_cpfp_packages =
+                    window.cpfp_transactions_count as f64 / window.transactions_count as f64
+                        * 100.0;
+            }
+            window
+        })
+        .collect()
+}
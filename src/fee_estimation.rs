@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use bitcoind_request::command::{
+    get_block_stats::{
+        GetBlockStatsCommand, GetBlockStatsCommandResponse, StatsArgumentChoices,
+        TargetBlockArgument,
+    },
+    CallableCommand,
+};
+use jsonrpc::serde_json::value::to_raw_value;
+use serde::Deserialize;
+
+use crate::{get_block_height, Client};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EstimateMode {
+    Conservative,
+    Economical,
+}
+
+impl EstimateMode {
+    fn as_rpc_argument(&self) -> &'static str {
+        match self {
+            EstimateMode::Conservative => "CONSERVATIVE",
+            EstimateMode::Economical => "ECONOMICAL",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EstimateSmartFeeResponse {
+    // in btc/kvB
+    feerate: Option<f64>,
+    errors: Option<Vec<String>>,
+    blocks: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmartFeeEstimate {
+    pub conf_target: u64,
+    pub mode: EstimateMode,
+    // in sat/vB. None when the node doesn't have enough data to estimate.
+    pub fee_rate: Option<f64>,
+    // block number where the estimate was found, which can differ from conf_target
+    pub blocks: u64,
+    pub errors: Vec<String>,
+}
+
+pub fn get_smart_fee_estimate(
+    client: &Client,
+    conf_target: u64,
+    mode: EstimateMode,
+) -> SmartFeeEstimate {
+    let response: EstimateSmartFeeResponse = client
+        .call(
+            "estimatesmartfee",
+            &[
+                to_raw_value(&conf_target).unwrap(),
+                to_raw_value(mode.as_rpc_argument()).unwrap(),
+            ],
+        )
+        .unwrap();
+    SmartFeeEstimate {
+        conf_target,
+        mode,
+        // btc/kvB to sat/vB
+        fee_rate: response.feerate.map(|fee_rate| fee_rate * 100_000.0),
+        blocks: response.blocks,
+        errors: response.errors.unwrap_or_default(),
+    }
+}
+
+// Estimates for every target, in both CONSERVATIVE and ECONOMICAL modes.
+pub fn get_smart_fee_estimates(client: &Client, conf_targets: &[u64]) -> Vec<SmartFeeEstimate> {
+    conf_targets
+        .iter()
+        .flat_map(|conf_target| {
+            [EstimateMode::Conservative, EstimateMode::Economical]
+                .into_iter()
+                .map(|mode| get_smart_fee_estimate(client, *conf_target, mode))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedFeeEstimate {
+    // height of the most recent block when the estimate was made
+    pub height: u64,
+    pub time: i64,
+    pub estimate: SmartFeeEstimate,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeEstimateAccuracy {
+    pub conf_target: u64,
+    pub mode: EstimateMode,
+    // estimates whose target blocks have all been mined
+    pub estimates_count: u64,
+    pub overestimates_count: u64,
+    pub underestimates_count: u64,
+    // mean of (estimated - needed) fee rate, in sat/vB
+    pub average_difference: f64,
+    // mean of estimated / needed fee rate
+    pub average_ratio: f64,
+}
+
+// Records fee estimates over time, then compares them with the fee rates that were actually
+// needed to get confirmed within the target.
+//
+//   let mut auditor = FeeEstimateAuditor::new(vec![1, 2, 6, 12, 144]);
+//   loop {
+//       auditor.record(&client);
+//       sleep(Duration::from_secs(60));
+//   }
+//   let accuracy = auditor.get_accuracy_report(&client);
+pub struct FeeEstimateAuditor {
+    pub conf_targets: Vec<u64>,
+    pub recorded_estimates: Vec<RecordedFeeEstimate>,
+}
+
+impl FeeEstimateAuditor {
+    pub fn new(conf_targets: Vec<u64>) -> Self {
+        FeeEstimateAuditor {
+            conf_targets,
+            recorded_estimates: vec![],
+        }
+    }
+
+    pub fn record(&mut self, client: &Client) {
+        let height = get_block_height(client);
+        let time = chrono::offset::Utc::now().timestamp();
+        for estimate in get_smart_fee_estimates(client, &self.conf_targets) {
+            self.recorded_estimates.push(RecordedFeeEstimate {
+                height,
+                time,
+                estimate,
+            });
+        }
+    }
+
+    // Estimates without a fee rate, or whose target blocks haven't all been mined yet, are
+    // skipped.
+    pub fn get_accuracy_report(&self, client: &Client) -> Vec<FeeEstimateAccuracy> {
+        let tip_height = get_block_height(client);
+        let mut min_fee_rate_by_height: HashMap<u64, Option<u64>> = HashMap::new();
+        let mut differences_by_target: HashMap<(u64, EstimateMode), Vec<(f64, f64)>> =
+            HashMap::new();
+        for recorded_estimate in &self.recorded_estimates {
+            let estimate = &recorded_estimate.estimate;
+            let estimated_fee_rate = match estimate.fee_rate {
+                Some(fee_rate) => fee_rate,
+                None => continue,
+            };
+            let last_target_height = recorded_estimate.height + estimate.conf_target;
+            if last_target_height > tip_height {
+                continue;
+            }
+            // The lowest fee rate that made it into any of the target blocks is what was needed
+            // to confirm in time.
+            let needed_fee_rate = (recorded_estimate.height + 1..=last_target_height)
+                .filter_map(|height| {
+                    *min_fee_rate_by_height
+                        .entry(height)
+                        .or_insert_with(|| get_min_fee_rate_for_block_at_height(client, height))
+                })
+                .min();
+            let needed_fee_rate = match needed_fee_rate {
+                Some(needed_fee_rate) => needed_fee_rate as f64,
+                None => continue,
+            };
+            differences_by_target
+                .entry((estimate.conf_target, estimate.mode))
+                .or_default()
+                .push((estimated_fee_rate, needed_fee_rate));
+        }
+        let mut accuracy_report: Vec<FeeEstimateAccuracy> = differences_by_target
+            .into_iter()
+            .map(|((conf_target, mode), fee_rates)| {
+                let estimates_count = fee_rates.len() as f64;
+                FeeEstimateAccuracy {
+                    conf_target,
+                    mode,
+                    estimates_count: fee_rates.len() as u64,
+                    overestimates_count: fee_rates
+                        .iter()
+                        .filter(|(estimated, needed)| estimated > needed)
+                        .count() as u64,
+                    underestimates_count: fee_rates
+                        .iter()
+                        .filter(|(estimated, needed)| estimated < needed)
+                        .count() as u64,
+                    average_difference: fee_rates
+                        .iter()
+                        .map(|(estimated, needed)| estimated - needed)
+                        .sum::<f64>()
+                        / estimates_count,
+                    average_ratio: fee_rates
+                        .iter()
+                        .map(|(estimated, needed)| estimated / needed.max(1.0))
+                        .sum::<f64>()
+                        / estimates_count,
+                }
+            })
+            .collect();
+        accuracy_report.sort_by_key(|accuracy| {
+            (
+                accuracy.conf_target,
+                accuracy.mode == EstimateMode::Economical,
+            )
+        });
+        accuracy_report
+    }
+}
+
+// None for blocks without any transaction other than the coinbase.
+fn get_min_fee_rate_for_block_at_height(client: &Client, height: u64) -> Option<u64> {
+    let client = &client.bitcoind_request_client;
+    let block_stats = GetBlockStatsCommand::new(TargetBlockArgument::Height(height))
+        .add_selective_stats(vec![
+            StatsArgumentChoices::Txs,
+            StatsArgumentChoices::MinFeeRate,
+        ])
+        .call(client);
+    let (transactions_count, min_fee_rate) = match block_stats.unwrap() {
+        GetBlockStatsCommandResponse::AllStats(response) => (response.txs, response.minfeerate),
+        GetBlockStatsCommandResponse::SelectiveStats(response) => {
+            (response.txs.unwrap(), response.minfeerate.unwrap())
+        }
+    };
+    if transactions_count > 1 {
+        Some(min_fee_rate)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::{
+    get_block_stats::{GetBlockStatsCommand, GetBlockStatsCommandResponse, TargetBlockArgument},
+    CallableCommand,
+};
+
+use crate::Client;
+
+// Percentiles of the feerate_percentiles returned by getblockstats.
+pub const FEE_RATE_PERCENTILES: [u8; 5] = [10, 25, 50, 75, 90];
+
+// All fee rates in sat/vB.
+#[derive(Debug, Clone)]
+pub struct BlockFeeRates {
+    pub height: u64,
+    pub time: u64,
+    // at the 10th, 25th, 50th, 75th and 90th percentile of the block's weight
+    pub fee_rate_percentiles: [u64; 5],
+    pub min_fee_rate: u64,
+    pub max_fee_rate: u64,
+    pub avg_fee_rate: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeRateInterval {
+    Hour,
+    Day,
+}
+
+impl FeeRateInterval {
+    pub(crate) fn seconds(&self) -> u64 {
+        match self {
+            FeeRateInterval::Hour => 60 * 60,
+            FeeRateInterval::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+// Aggregate of the blocks mined within one interval. All fee rates in sat/vB.
+#[derive(Debug, Clone)]
+pub struct FeeRateWindow {
+    // unix timestamp of the start of the interval
+    pub start_time: u64,
+    pub blocks_count: u64,
+    // median, across the blocks, of each of the 10th, 25th, 50th, 75th and 90th percentiles. The
+    // middle one is the median of the blocks' median fee rates.
+    pub median_fee_rate_percentiles: [f64; 5],
+    pub min_fee_rate: u64,
+    pub max_fee_rate: u64,
+    // mean of the blocks' average fee rates
+    pub avg_fee_rate: f64,
+}
+
+pub fn get_fee_rates_for_block_at_height(client: &Client, height: u64) -> BlockFeeRates {
+    let client = &client.bitcoind_request_client;
+    // Not selecting the stats, as avgfeerate can't be selected through StatsArgumentChoices.
+    let block_stats = GetBlockStatsCommand::new(TargetBlockArgument::Height(height)).call(client);
+    match block_stats.unwrap() {
+        GetBlockStatsCommandResponse::AllStats(response) => BlockFeeRates {
+            height: response.height,
+            time: response.time,
+            fee_rate_percentiles: response.feerate_percentiles,
+            min_fee_rate: response.minfeerate,
+            max_fee_rate: response.maxfeerate,
+            avg_fee_rate: response.avgfeerate,
+        },
+        GetBlockStatsCommandResponse::SelectiveStats(response) => BlockFeeRates {
+            height: response.height.unwrap(),
+            time: response.time.unwrap(),
+            fee_rate_percentiles: response.feerate_percentiles.unwrap(),
+            min_fee_rate: response.minfeerate.unwrap(),
+            max_fee_rate: response.maxfeerate.unwrap(),
+            avg_fee_rate: response.avgfeerate.unwrap(),
+        },
+    }
+}
+
+// takes a long time
+pub fn get_fee_rates_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> Vec<BlockFeeRates> {
+    heights
+        .map(|height| get_fee_rates_for_block_at_height(client, height))
+        .collect()
+}
+
+fn get_median(mut values: Vec<u64>) -> f64 {
+    values.sort_unstable();
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) as f64 / 2.0
+    } else {
+        values[middle] as f64
+    }
+}
+
+// Groups the blocks by the interval their timestamp falls in. Ordered by time.
+pub fn get_fee_rate_windows(
+    block_fee_rates: &[BlockFeeRates],
+    interval: FeeRateInterval,
+) -> Vec<FeeRateWindow> {
+    let mut block_fee_rates_by_interval: BTreeMap<u64, Vec<&BlockFeeRates>> = BTreeMap::new();
+    for fee_rates in block_fee_rates {
+        let start_time = fee_rates.time - fee_rates.time % interval.seconds();
+        block_fee_rates_by_interval
+            .entry(start_time)
+            .or_default()
+            .push(fee_rates);
+    }
+    block_fee_rates_by_interval
+        .into_iter()
+        .map(|(start_time, fee_rates)| {
+            let mut median_fee_rate_percentiles = [0.0; 5];
+            for (i, median_fee_rate_percentile) in
+                median_fee_rate_percentiles.iter_mut().enumerate()
+            {
+                *median_fee_rate_percentile = get_median(
+                    fee_rates
+                        .iter()
+                        .map(|fee_rates| fee_rates.fee_rate_percentiles[i])
+                        .collect(),
+                );
+            }
+            let total_avg_fee_rate: u64 = fee_rates
+                .iter()
+                .map(|fee_rates| fee_rates.avg_fee_rate)
+                .sum();
+            FeeRateWindow {
+                start_time,
+                blocks_count: fee_rates.len() as u64,
+                median_fee_rate_percentiles,
+                min_fee_rate: fee_rates
+                    .iter()
+                    .map(|fee_rates| fee_rates.min_fee_rate)
+                    .min()
+                    .unwrap(),
+                max_fee_rate: fee_rates
+                    .iter()
+                    .map(|fee_rates| fee_rates.max_fee_rate)
+                    .max()
+                    .unwrap(),
+                avg_fee_rate: total_avg_fee_rate as f64 / fee_rates.len() as f64,
+            }
+        })
+        .collect()
+}
+
+// takes a long time
+pub fn get_fee_rate_windows_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    interval: FeeRateInterval,
+) -> Vec<FeeRateWindow> {
+    let block_fee_rates = get_fee_rates_for_block_range(client, heights);
+    get_fee_rate_windows(&block_fee_rates, interval)
+}
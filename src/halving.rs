@@ -0,0 +1,115 @@
+use crate::network::{get_network, Network};
+use crate::supply::{get_block_subsidy_at_height, get_issued_supply_at_height, MAX_HALVINGS};
+use crate::{get_block_height, get_subsidy_for_block_at_height, Client};
+
+// 21 million btc, in sats.
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+const TARGET_SECONDS_PER_BLOCK: u64 = 10 * 60;
+// 365.25 days of 10 minute blocks
+const BLOCKS_PER_YEAR: f64 = 365.25 * 24.0 * 6.0;
+
+#[derive(Debug, Clone)]
+pub struct SubsidyEra {
+    // 0 for the first era, incremented at every halving
+    pub era: u64,
+    pub start_height: u64,
+    pub end_height: u64,
+    pub subsidy: u64,
+    // supply issued by all the blocks before this era
+    pub supply_at_start: u64,
+    // supply issued once the last block of this era is mined
+    pub supply_at_end: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HalvingCountdown {
+    pub network: Network,
+    pub height: u64,
+    pub current_era: u64,
+    pub current_subsidy: u64,
+    pub next_subsidy: u64,
+    pub next_halving_height: u64,
+    pub blocks_until_next_halving: u64,
+    // assumes 10 minute blocks
+    pub estimated_seconds_until_next_halving: u64,
+    pub estimated_next_halving_timestamp: i64,
+    pub issued_supply: u64,
+    pub supply_at_next_halving: u64,
+    // between 0.0 and 100.0, of the 21 million btc cap
+    pub percent_of_max_money_issued: f64,
+    // yearly new supply as a percent of the existing supply
+    pub current_annual_inflation_rate: f64,
+    pub next_era_annual_inflation_rate: f64,
+}
+
+// Every subsidy era until the subsidy reaches zero.
+pub fn get_issuance_schedule(network: Network) -> Vec<SubsidyEra> {
+    let blocks_per_halving = network.blocks_per_halving();
+    (0..MAX_HALVINGS)
+        .map(|era| {
+            let start_height = era * blocks_per_halving;
+            let end_height = start_height + blocks_per_halving - 1;
+            SubsidyEra {
+                era,
+                start_height,
+                end_height,
+                subsidy: get_block_subsidy_at_height(network, start_height),
+                supply_at_start: if era == 0 {
+                    0
+                } else {
+                    get_issued_supply_at_height(network, start_height - 1)
+                },
+                supply_at_end: get_issued_supply_at_height(network, end_height),
+            }
+        })
+        .take_while(|subsidy_era| subsidy_era.subsidy > 0)
+        .collect()
+}
+
+// First height after the given one at which the subsidy halves.
+pub fn get_next_halving_height(network: Network, height: u64) -> u64 {
+    (height / network.blocks_per_halving() + 1) * network.blocks_per_halving()
+}
+
+fn get_annual_inflation_rate(subsidy: u64, supply: u64) -> f64 {
+    subsidy as f64 * BLOCKS_PER_YEAR / supply as f64 * 100.0
+}
+
+pub fn get_halving_countdown(client: &Client) -> HalvingCountdown {
+    let network = get_network(client);
+    let height = get_block_height(client);
+    let current_era = height / network.blocks_per_halving();
+    let next_halving_height = get_next_halving_height(network, height);
+    let blocks_until_next_halving = next_halving_height - height;
+    let estimated_seconds_until_next_halving = blocks_until_next_halving * TARGET_SECONDS_PER_BLOCK;
+    let issued_supply = get_issued_supply_at_height(network, height);
+    let supply_at_next_halving = get_issued_supply_at_height(network, next_halving_height - 1);
+    let current_subsidy = get_subsidy_for_block_at_height(client, height);
+    let next_subsidy = get_block_subsidy_at_height(network, next_halving_height);
+    HalvingCountdown {
+        network,
+        height,
+        current_era,
+        current_subsidy,
+        next_subsidy,
+        next_halving_height,
+        blocks_until_next_halving,
+        estimated_seconds_until_next_halving,
+        estimated_next_halving_timestamp: chrono::offset::Utc::now().timestamp()
+            + estimated_seconds_until_next_halving as i64,
+        issued_supply,
+        supply_at_next_halving,
+        percent_of_max_money_issued: issued_supply as f64 / MAX_MONEY as f64 * 100.0,
+        current_annual_inflation_rate: get_annual_inflation_rate(current_subsidy, issued_supply),
+        next_era_annual_inflation_rate: get_annual_inflation_rate(
+            next_subsidy,
+            supply_at_next_halving,
+        ),
+    }
+}
+
+pub fn get_blocks_count_until_next_halving(client: &Client) -> u64 {
+    let network = get_network(client);
+    let height = get_block_height(client);
+    get_next_halving_height(network, height) - height
+}
@@ -1,5 +1,4 @@
 #![allow(unused_imports)]
-use bitcoin_address::is_segwit_v0;
 use bitcoin_transaction_utils::is_transaction_hex_segwit;
 use bitcoind_request::command::{
     get_best_block_hash::GetBestBlockHashCommand,
@@ -28,12 +27,144 @@ use bitcoind_request::command::{
 };
 
 use bitcoind_request::{Blockhash, BlockhashHexEncoded};
+mod batching;
+mod block_template;
+mod burned;
+mod change;
 mod client;
-
+mod coinbase;
+mod data_embedding;
+mod empty_blocks;
+mod fee_bumping;
+mod fee_estimation;
+mod fee_rates;
+mod halving;
+mod mempool;
+mod mempool_events;
+mod mining_centralization;
+mod mining_pool;
+mod network;
+mod op_return;
+mod prevouts;
+mod script;
+mod script_types;
+mod sha256;
+mod supply;
+mod taproot;
+mod tx_watcher;
+mod version_bits;
+mod weight;
+
+pub use batching::{
+    get_transaction_shape, get_transaction_shape_windows_for_block_range,
+    get_transaction_shape_windows_over_last_24_hours, TransactionShape, TransactionShapeStats,
+    TransactionShapeWindow, BATCH_MIN_OUTPUTS, CONSOLIDATION_MIN_INPUTS,
+};
+pub use block_template::{
+    get_block_template, get_block_template_audit, get_pool_template_audit_summaries,
+    get_projected_block_template, BlockTemplate, BlockTemplateAudit, BlockTemplateAuditor,
+    BlockTemplateSource, PoolTemplateAuditSummary, TemplateTransaction,
+};
+pub use burned::{
+    get_burned_value_for_block_at_height, get_burned_value_report_for_block_range, BurnedValue,
+    BurnedValueReport, YearlyBurnedValue, DEFAULT_BURN_ADDRESSES,
+};
+pub use change::{
+    get_change_output_likelihoods, get_likely_change_output, get_payment_stats_for_block_range,
+    get_payment_stats_over_last_24_hours, ChangeHeuristic, ChangeOutputLikelihood,
+    PaymentDailyStats, CHANGE_CONFIDENCE_THRESHOLD,
+};
+use change::{get_outputs_of_transaction, get_payment_outputs};
 use chrono::{DateTime, Duration, TimeZone, Timelike, Utc};
 pub use client::Client;
+pub use coinbase::{
+    get_coinbase_audit_for_block_at_height, get_coinbase_audits_for_block_range,
+    get_total_under_claimed_reward, get_under_claimed_reward_for_block_at_height,
+    get_under_claimed_rewards_for_block_range, CoinbaseAudit, UnderClaimedReward,
+};
+pub use data_embedding::{
+    get_data_embedding_for_block_at_height, get_data_embedding_for_block_range,
+    get_data_embedding_kind, get_data_embedding_over_last_24_hours, get_data_embedding_windows,
+    get_inscriptions, BlockDataEmbedding, DataEmbeddingKind, DataEmbeddingUsage,
+    DataEmbeddingWindow, Inscription, MAX_SCRIPT_ELEMENT_SIZE, MAX_STANDARD_OP_RETURN_SIZE,
+};
+pub use empty_blocks::{
+    get_empty_blocks_for_block_range, get_empty_blocks_report_for_block_range, EmptyBlock,
+    EmptyBlockThreshold, EmptyBlocksReport,
+};
+pub use fee_bumping::{
+    get_cpfp_packages_for_block_at_height, get_cpfp_packages_for_block_range,
+    get_fee_bumping_windows, BlockCpfpPackages, CpfpPackage, FeeBumpingWindow, Replacement,
+    ReplacementKind, ReplacementTracker,
+};
+pub use fee_estimation::{
+    get_smart_fee_estimate, get_smart_fee_estimates, EstimateMode, FeeEstimateAccuracy,
+    FeeEstimateAuditor, RecordedFeeEstimate, SmartFeeEstimate,
+};
+pub use fee_rates::{
+    get_fee_rate_windows, get_fee_rate_windows_for_block_range, get_fee_rates_for_block_at_height,
+    get_fee_rates_for_block_range, BlockFeeRates, FeeRateInterval, FeeRateWindow,
+    FEE_RATE_PERCENTILES,
+};
+pub use halving::{
+    get_blocks_count_until_next_halving, get_halving_countdown, get_issuance_schedule,
+    get_next_halving_height, HalvingCountdown, SubsidyEra, MAX_MONEY,
+};
 use jsonrpc::simple_http::{self, SimpleHttpTransport};
+pub use mempool::{
+    get_fee_rate_histogram, get_mempool_entries, get_mempool_entry, get_mempool_info,
+    get_mempool_report, get_projected_blocks, FeeRateBucket, MempoolEntry, MempoolInfo,
+    MempoolReport, ProjectedBlock, FEE_RATE_HISTOGRAM_BUCKETS, MAX_BLOCK_VSIZE,
+};
+pub use mempool_events::{MempoolEvent, MempoolEventStream};
+pub use mining_centralization::{
+    get_herfindahl_hirschman_index, get_longest_pool_run, get_mining_centralization_report,
+    get_mining_centralization_report_for_block_range, get_nakamoto_coefficient, get_pool_luck,
+    get_pool_luck_for_block_range, MiningCentralizationReport, PoolLuck, PoolRun,
+};
+pub use mining_pool::{
+    get_pool_for_block_at_height, get_pool_shares, get_pool_shares_for_block_range,
+    get_pools_for_block_range, BlockPool, PoolAttributionMethod, PoolDefinition, PoolDefinitions,
+    PoolDefinitionsError, PoolShare,
+};
+pub use network::{get_network, Network};
+pub use op_return::{
+    get_op_return_payload, get_op_return_report_for_block_range,
+    get_op_return_report_over_last_24_hours, OpReturnProtocol, OpReturnProtocolDefinitions,
+    OpReturnProtocolDefinitionsError, OpReturnProtocolUsage, OpReturnReport, PayloadSizeBucket,
+    DEFAULT_OP_RETURN_PROTOCOL_DEFINITIONS, PAYLOAD_SIZE_HISTOGRAM_BUCKETS, UNKNOWN_PROTOCOL_NAME,
+};
+pub use prevouts::{
+    BlockWithPrevouts, Prevout, PrevoutError, PrevoutResolutionStats, PrevoutResolver,
+    DEFAULT_PREVOUT_CACHE_CAPACITY,
+};
+pub use script_types::{
+    get_script_type, get_script_type_breakdown_for_block_range,
+    get_script_type_breakdown_over_last_24_hours, get_spent_script_type, ScriptType,
+    ScriptTypeBreakdown, ScriptTypeUsage,
+};
+use std::ops::RangeInclusive;
 use std::{env, time::SystemTimeError};
+pub use supply::{
+    get_analytic_money_supply, get_block_subsidy_at_height, get_issued_supply_at_height,
+    get_money_supply_reconciliation, get_supply_losses_for_block_range, reconcile_money_supply,
+    MoneySupplyReconciliation, SupplyLosses,
+};
+pub use taproot::{
+    get_taproot_spend, get_taproot_usage_for_block_range, get_taproot_usage_over_last_24_hours,
+    TaprootDailyUsage, TaprootSpend, TaprootSpendPath,
+};
+pub use tx_watcher::{TxStatus, TxStatusTransition, TxWatcher};
+pub use version_bits::{
+    get_block_version_at_height, get_current_signaling_period_heights, get_deployments,
+    get_signaled_bits, get_version_bits_report, get_version_bits_report_for_block_range,
+    Bip9Deployment, Bip9Statistics, BitSignaling, BlockVersion, Deployment, DeploymentSignaling,
+    PoolSignaling, VersionBitsReport,
+};
+pub use weight::{
+    get_block_space_windows_for_block_range, get_block_space_windows_over_last_24_hours,
+    get_transaction_weight, BlockSpaceWindow, TransactionWeight, MAX_BLOCK_WEIGHT,
+};
 
 const BLOCKS_PER_DIFFICULTY_PERIOD: u64 = 2016;
 
@@ -61,6 +192,36 @@ fn timestamp_is_from_more_than_90_days_ago(timestamp: i64) -> bool {
     datetime_of_block < datetime_90_days_ago
 }
 
+fn btc_to_sats(btc: f64) -> u64 {
+    (btc * 100_000_000.0).round() as u64
+}
+
+fn get_block_with_transactions_at_height(client: &Client, height: u64) -> get_block::Block {
+    let client = &client.bitcoind_request_client;
+    let blockhash = GetBlockHashCommand::new(height).call(client).unwrap().0;
+    let get_block_response = GetBlockCommand::new(blockhash)
+        .verbosity(GetBlockCommandVerbosity::BlockObjectWithTransactionInformation)
+        .call(client);
+    match get_block_response.unwrap() {
+        GetBlockCommandResponse::Block(block) => block,
+        GetBlockCommandResponse::BlockHash(_) => unreachable!(),
+    }
+}
+
+fn get_transactions_of_block(
+    block_transactions_responses: Vec<GetBlockCommandTransactionResponse>,
+) -> Vec<DecodeRawTransactionResponse> {
+    block_transactions_responses
+        .into_iter()
+        .map(
+            |block_transaction_response| match block_transaction_response {
+                GetBlockCommandTransactionResponse::Raw(transaction) => transaction,
+                GetBlockCommandTransactionResponse::Id(_) => unreachable!(),
+            },
+        )
+        .collect()
+}
+
 pub fn get_block_height(client: &Client) -> u64 {
     let client = &client.bitcoind_request_client;
     let block_count = GetBlockCountCommand::new().call(client);
@@ -215,6 +376,20 @@ fn get_timestamp_of_block_at_height(client: &Client, height: u64) -> u64 {
     time
 }
 
+// Heights of the blocks mined within the last 24 hours, always including the most recent block.
+pub fn get_block_heights_over_last_24_hours(client: &Client) -> RangeInclusive<u64> {
+    let block_count = get_block_height(client);
+    let mut start_height = block_count;
+    while start_height > 0 {
+        let time_of_previous_block = get_timestamp_of_block_at_height(client, start_height - 1);
+        if timestamp_is_from_more_than_24_hours_ago(time_of_previous_block as i64) {
+            break;
+        }
+        start_height -= 1;
+    }
+    start_height..=block_count
+}
+
 // takes a long time
 pub fn get_total_fee_for_24_hours(client: &Client) -> u64 {
     let last_block_height = get_block_height(&client);
@@ -429,223 +604,254 @@ pub fn get_block_subsidy_of_most_recent_block(client: &Client) -> u64 {
     block_subsidy
 }
 
+// How get_percent_of_vouts_used_segwit_over_last_24_hours counts segwit usage. Each method that's
+// turned off leaves the numbers it produces as None.
+#[derive(Debug, Clone)]
+pub struct SegwitAdoptionOptions {
+    // transactions serialized with witness data (https://bitbo.io/)
+    pub based_on_transaction_hex: bool,
+    // transactions paying to a segwit output
+    pub based_on_vouts: bool,
+    // transactions spending a segwit output too. Resolves the spent outputs with the
+    // PrevoutResolver, which needs txindex on nodes before 23.0.
+    pub based_on_vouts_and_vins: bool,
+    // leave out the output detected as change by the change heuristics, as it isn't a payment
+    // (https://transactionfee.info/charts/payments-spending-segwit/)
+    pub factor_in_change_address: bool,
+    pub include_coinbase_transaction: bool,
+}
+
+impl Default for SegwitAdoptionOptions {
+    fn default() -> Self {
+        SegwitAdoptionOptions {
+            based_on_transaction_hex: true,
+            based_on_vouts: true,
+            based_on_vouts_and_vins: true,
+            factor_in_change_address: true,
+            include_coinbase_transaction: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SegwitShare {
+    pub count: u64,
+    pub total: u64,
+}
+
+impl SegwitShare {
+    // between 0.0 and 1.0, like the other shares (percent_ fields are between 0.0 and 100.0).
+    // None when there's nothing to count.
+    pub fn share(&self) -> Option<f64> {
+        (self.total > 0).then(|| self.count as f64 / self.total as f64)
+    }
+}
+
+// Outputs or spent inputs of each kind of segwit.
+#[derive(Debug, Clone)]
+pub struct SegwitSplit {
+    // P2SH-P2WPKH and P2SH-P2WSH. None for outputs, as a nested segwit output looks like any
+    // other P2SH output until it's spent.
+    pub nested_segwit: Option<SegwitShare>,
+    // P2WPKH and P2WSH
+    pub native_segwit_v0: SegwitShare,
+    pub taproot: SegwitShare,
+}
+
+// The transactions and payments counts keep the definition these numbers always had (the one of
+// bitcoin_address::is_segwit_v0): native segwit v0 and P2SH, nested segwit or not, and no taproot.
+// The splits tell the kinds of segwit apart.
+#[derive(Debug)]
+pub struct SegwitAdoptionReport {
+    pub options: SegwitAdoptionOptions,
+    pub blocks_count: u64,
+    pub transactions_count: u64,
+    // based_on_transaction_hex (https://bitbo.io/)
+    pub transactions_with_witness_data: Option<SegwitShare>,
+    // based_on_vouts
+    pub transactions_with_a_segwit_vout: Option<SegwitShare>,
+    pub output_split: Option<SegwitSplit>,
+    // based_on_vouts_and_vins
+    pub transactions_with_a_segwit_vin_or_vout: Option<SegwitShare>,
+    // https://transactionfee.info/charts/transactions-spending-segwit/
+    pub segwit_spending_transactions: Option<SegwitShare>,
+    // payments are outputs but OP_RETURN ones, minus the change output if factor_in_change_address
+    // (https://transactionfee.info/charts/payments-spending-segwit/)
+    pub payments_spending_segwit: Option<SegwitShare>,
+    pub spent_input_split: Option<SegwitSplit>,
+    // why the based_on_vouts_and_vins numbers are None even though they were asked for
+    pub prevout_error: Option<PrevoutError>,
+}
+
+// Script type of the output spent by the input.
+fn get_spent_script_type_of_vin(prevout: &Prevout, vin: &get_block::NonCoinbaseVin) -> ScriptType {
+    // Spends without a witness can't be segwit, whatever the redeem script says.
+    let has_witness = match &vin.txinwitness {
+        Some(witness) => !witness.is_empty(),
+        None => false,
+    };
+    match get_spent_script_type(
+        &script::decode_hex(&prevout.script_pub_key_hex),
+        &script::decode_hex(&vin.script_sig.hex),
+    ) {
+        script_type if script_type.is_segwit() && !has_witness => ScriptType::NonStandard,
+        script_type => script_type,
+    }
+}
+
+// P2SH counts whether it wraps segwit or not, as it can't be told apart for outputs.
+fn is_segwit_v0_or_p2sh(script_type: ScriptType) -> bool {
+    matches!(
+        script_type,
+        ScriptType::P2wpkh
+            | ScriptType::P2wsh
+            | ScriptType::P2sh
+            | ScriptType::P2shP2wpkh
+            | ScriptType::P2shP2wsh
+    )
+}
+
+fn get_segwit_split(script_types: &[ScriptType], include_nested_segwit: bool) -> SegwitSplit {
+    let total = script_types.len() as u64;
+    let count = |matches: &dyn Fn(&ScriptType) -> bool| SegwitShare {
+        count: script_types
+            .iter()
+            .filter(|script_type| matches(script_type))
+            .count() as u64,
+        total,
+    };
+    SegwitSplit {
+        nested_segwit: if include_nested_segwit {
+            Some(count(&|script_type| {
+                matches!(script_type, ScriptType::P2shP2wpkh | ScriptType::P2shP2wsh)
+            }))
+        } else {
+            None
+        },
+        native_segwit_v0: count(&|script_type| {
+            matches!(script_type, ScriptType::P2wpkh | ScriptType::P2wsh)
+        }),
+        taproot: count(&|script_type| *script_type == ScriptType::P2tr),
+    }
+}
+
 //// takes a long time
-struct Conf {
-    based_on_transaction_hex: bool,
-    based_on_vouts_and_vins: bool,
-    based_on_vouts: bool,
-    factor_in_change_address: bool,
-    include_coinbase_transaction: bool,
-}
-
-// NOTE: Currently is optimized or only works for basing it off the transaction hex. Because of the
-// 'continue' the vouts and vins won't even be taken into consideration. To do them also, remove
-// the 'continue' and 'break's
-// TODO: REMOVE THE break AND continue, unless you incorporate the configs above.
-// TODO: SPLIT BETWEEN segwit and segwit native. For example, this tranasaction is segwit (notic
-// the yellow tag, but not segwit native). See new address functions below.
 pub fn get_percent_of_vouts_used_segwit_over_last_24_hours(
     client: &Client,
-) -> (f64, f64, f64, f64, f64) {
-    let is_segwit = is_segwit_v0;
-    let block_count = get_block_height(client);
-
-    // defaults to current time
-    let mut next_block_timestamp: u64 = get_timestamp_of_block_at_height(client, block_count);
-    let mut traversed_blocks_count = 0;
-    let mut vouts_count_minus_change_vout: u64 = 0;
-    let mut segwit_vouts_count: u64 = 0;
-    let mut transactions_count_not_including_coinbase: u64 = 0;
-    let mut segwit_spending_transactions_count_not_including_coinbase: u64 = 0;
-    let mut payments_from_segwit_spending_transactions_count_not_including_coinbase: u64 = 0;
-    let mut transactions_count_including_coinbase: u64 = 0;
-    let mut transactions_segwit_count_based_on_vouts_not_including_coinbase: u64 = 0;
-    let mut transactions_segwit_count_based_on_transaction_hex_not_including_coinbase: u64 = 0;
-    let mut transactions_segwit_count_based_on_vins_or_vouts_not_including_coinbase: u64 = 0;
-    // Calculate fee while the blocktime is within the 24 hour window.
-    while !timestamp_is_from_more_than_24_hours_ago(next_block_timestamp as i64) {
-        let height = block_count - traversed_blocks_count;
-        // get vouts of block at height
-        let bitcoind_request_client = &client.bitcoind_request_client;
-        // Get all transactions
-        let block_stats = GetBlockStatsCommand::new(TargetBlockArgument::Height(height))
-            .add_selective_stats(vec![StatsArgumentChoices::Blockhash])
-            .call(bitcoind_request_client);
-        let blockhash = match block_stats.unwrap() {
-            GetBlockStatsCommandResponse::AllStats(response) => response.blockhash,
-            GetBlockStatsCommandResponse::SelectiveStats(response) => response.blockhash.unwrap(),
-        };
-        let get_block_response = GetBlockCommand::new(Blockhash(blockhash))
-            .verbosity(GetBlockCommandVerbosity::BlockObjectWithTransactionInformation)
-            .call(&bitcoind_request_client);
-        let block_transactions_responses: Vec<GetBlockCommandTransactionResponse> =
-            match get_block_response.unwrap() {
-                GetBlockCommandResponse::Block(block) => block.tx,
-                _ => todo!(),
-            };
-        let transactions: Vec<DecodeRawTransactionResponse> = block_transactions_responses
-            .into_iter()
-            .map(
-                |block_transaction_response| match block_transaction_response {
-                    GetBlockCommandTransactionResponse::Raw(transaction) => transaction,
-                    GetBlockCommandTransactionResponse::Id(transaction_id) => todo!(),
-                },
-            )
-            .collect();
-        // Loop through transactions
-        for transaction in transactions.into_iter() {
-            transactions_count_including_coinbase = transactions_count_including_coinbase + 1;
-            if !transaction.is_coinbase_transaction() {
-                transactions_count_not_including_coinbase =
-                    transactions_count_not_including_coinbase + 1;
-                let transaction_hex = transaction.hex;
-                if is_transaction_hex_segwit(&transaction_hex) {
-                    transactions_segwit_count_based_on_transaction_hex_not_including_coinbase =
-                        transactions_segwit_count_based_on_transaction_hex_not_including_coinbase
-                            + 1;
-                    // TODO: This would optimize it to be much quicker if we only care about the
-                    // transaction hex
-                    // continue;
-                }
-                let vouts = transaction.vout;
-                let vins = transaction.vin;
-                let vouts_count: u64 = vouts.len() as u64;
-                let vouts_count_minus_change_vout_for_transaction = if vouts_count == 1 {
-                    vouts_count
-                } else {
-                    vouts_count - 1
-                };
-                // Factor in change address: https://transactionfee.info/charts/payments-spending-segwit/
-                if vouts_count == 1 {
-                    vouts_count_minus_change_vout = vouts_count_minus_change_vout + vouts_count;
-                } else {
-                    vouts_count_minus_change_vout = vouts_count_minus_change_vout + vouts_count - 1;
-                }
-                let mut is_segwit_transaction_based_on_vouts = false;
-                let mut is_segwit_transaction_based_on_vins = false;
-                for vout in vouts.iter() {
-                    let is_segwit = match &vout.script_pub_key.address {
-                        Some(address) => is_segwit(&address),
-                        None => false,
-                    };
-                    if is_segwit {
-                        segwit_vouts_count = segwit_vouts_count + 1;
-                        is_segwit_transaction_based_on_vouts = true;
-                        // TODO: This would optimize it to be much quicker we didn't care about
-                        // vins, UNLESS there were not  vout addresses that matched our segwith
-                        // calculation
-                        //break;
-                    } else {
-                        //println!(
-                        //    "NOT SEGWIT BASED ON VOUTS: {:#?}",
-                        //    &vout.script_pub_key.address
-                        //);
-                    }
-                }
-                // NOTE: OPTIMIZATION for certain code paths
-                //if !is_segwit_transaction_based_on_vouts {
-                let mut vins_count = 0;
-
-                for vin in vins.iter() {
-                    match &vin {
-                        get_block::Vin::Coinbase(_) => {}
-                        get_block::Vin::NonCoinbase(v) => {
-                            vins_count = vins_count + 1;
-                            let txid = &v.txid;
-                            let vout_index = v.vout;
-                            let transaction = GetRawTransactionCommand::new(txid.clone())
-                                .verbose(true)
-                                .call(bitcoind_request_client);
-
-                            let vout_address = match transaction.unwrap() {
-                                GetRawTransactionCommandResponse::SerializedHexEncodedData(_s) => {
-                                    todo!()
-                                }
-                                GetRawTransactionCommandResponse::Transaction(transaction) => {
-                                    let address = &transaction.vout[vout_index as usize]
-                                        .script_pub_key
-                                        .address;
-                                    let a = address.clone();
-                                    a
-                                }
-                            };
-
-                            let is_segwit = match &vout_address {
-                                Some(address) => is_segwit(address),
-                                None => false,
-                            };
-                            if is_segwit {
-                                is_segwit_transaction_based_on_vins = true;
-                                // break;
-                            } else {
-                                //println!("NOT SEGWIT BASED ON VINS: {:#?}", &vout_address);
-                            }
-                        }
+    options: &SegwitAdoptionOptions,
+) -> SegwitAdoptionReport {
+    let heights = get_block_heights_over_last_24_hours(client);
+    let based_on_vouts = options.based_on_vouts || options.based_on_vouts_and_vins;
+    let mut blocks_count: u64 = 0;
+    let mut transactions_count: u64 = 0;
+    let mut transactions_with_witness_data_count: u64 = 0;
+    let mut transactions_with_a_segwit_vout_count: u64 = 0;
+    let mut transactions_with_a_segwit_vin_or_vout_count: u64 = 0;
+    let mut segwit_spending_transactions_count: u64 = 0;
+    let mut payments_count: u64 = 0;
+    let mut payments_spending_segwit_count: u64 = 0;
+    let mut vout_script_types: Vec<ScriptType> = vec![];
+    let mut spent_script_types: Vec<ScriptType> = vec![];
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    let mut prevout_error: Option<PrevoutError> = None;
+    for height in heights {
+        blocks_count += 1;
+        // stop resolving prevouts after the first failure, the vin numbers would be partial anyway
+        let (transactions, block_prevouts) =
+            if options.based_on_vouts_and_vins && prevout_error.is_none() {
+                let block = prevout_resolver.get_block_with_prevouts_at_height(client, height);
+                let block_prevouts = match block.prevouts {
+                    Ok(block_prevouts) => block_prevouts,
+                    Err(error) => {
+                        prevout_error = Some(error);
+                        vec![]
                     }
-                }
-
-                //}
+                };
+                (block.transactions, block_prevouts)
+            } else {
+                let block = get_block_with_transactions_at_height(client, height);
+                (get_transactions_of_block(block.tx), vec![])
+            };
+        for (index, transaction) in transactions.into_iter().enumerate() {
+            if transaction.is_coinbase_transaction() && !options.include_coinbase_transaction {
+                continue;
+            }
+            transactions_count += 1;
+            if options.based_on_transaction_hex && is_transaction_hex_segwit(&transaction.hex) {
+                transactions_with_witness_data_count += 1;
+            }
 
-                if is_segwit_transaction_based_on_vouts {
-                    transactions_segwit_count_based_on_vouts_not_including_coinbase =
-                        transactions_segwit_count_based_on_vouts_not_including_coinbase + 1;
-                } else {
-                    //jprintln!("NOT SEGWIT: {:#?}", transaction.txid);
+            let mut has_segwit_vout = false;
+            if based_on_vouts {
+                for vout in &transaction.vout {
+                    let script_type =
+                        get_script_type(&script::decode_hex(&vout.script_pub_key.hex));
+                    has_segwit_vout = has_segwit_vout || is_segwit_v0_or_p2sh(script_type);
+                    vout_script_types.push(script_type);
                 }
-
-                // https://transactionfee.info/charts/payments-spending-segwit/
-                if is_segwit_transaction_based_on_vins {
-                    segwit_spending_transactions_count_not_including_coinbase =
-                        segwit_spending_transactions_count_not_including_coinbase + 1;
-
-                    payments_from_segwit_spending_transactions_count_not_including_coinbase =
-                        payments_from_segwit_spending_transactions_count_not_including_coinbase
-                            + vouts_count_minus_change_vout_for_transaction;
+                if has_segwit_vout {
+                    transactions_with_a_segwit_vout_count += 1;
                 }
+            }
 
-                if is_segwit_transaction_based_on_vouts || is_segwit_transaction_based_on_vins {
-                    transactions_segwit_count_based_on_vins_or_vouts_not_including_coinbase =
-                        transactions_segwit_count_based_on_vins_or_vouts_not_including_coinbase + 1;
+            if options.based_on_vouts_and_vins && prevout_error.is_none() {
+                let mut has_segwit_vin = false;
+                let non_coinbase_vins = transaction.vin.iter().filter_map(|vin| match vin {
+                    get_block::Vin::NonCoinbase(vin) => Some(vin),
+                    get_block::Vin::Coinbase(_) => None,
+                });
+                for (vin, prevout) in non_coinbase_vins.zip(&block_prevouts[index]) {
+                    let script_type = get_spent_script_type_of_vin(prevout, vin);
+                    has_segwit_vin = has_segwit_vin || is_segwit_v0_or_p2sh(script_type);
+                    spent_script_types.push(script_type);
+                }
+                let outputs = get_outputs_of_transaction(&transaction);
+                let change_output = if options.factor_in_change_address {
+                    get_likely_change_output(&get_change_output_likelihoods(
+                        &block_prevouts[index],
+                        &outputs,
+                    ))
                 } else {
-                    //println!("NOT SEGWIT: {:#?}", transaction.txid);
+                    None
+                };
+                let payments_count_for_transaction =
+                    get_payment_outputs(&outputs, change_output).len() as u64;
+                payments_count += payments_count_for_transaction;
+                if has_segwit_vin {
+                    segwit_spending_transactions_count += 1;
+                    payments_spending_segwit_count += payments_count_for_transaction;
+                }
+                if has_segwit_vin || has_segwit_vout {
+                    transactions_with_a_segwit_vin_or_vout_count += 1;
                 }
             }
         }
-
-        traversed_blocks_count = traversed_blocks_count + 1;
-
-        let next_block_height_to_calculate = block_count - traversed_blocks_count;
-        let time = get_timestamp_of_block_at_height(client, next_block_height_to_calculate);
-
-        next_block_timestamp = time as u64;
     }
 
-    // % of payments that are segwit, where payments are vouts. NOTE: We factor in change address
-    // segwit_vouts_count as f64 / vouts_count_minus_change_vout as f64
-    //
-    // % of transactions that are segwit, not including coinase
-    let percent_of_transactions_with_a_segwit_vout =
-        transactions_segwit_count_based_on_vouts_not_including_coinbase as f64
-            / transactions_count_not_including_coinbase as f64;
-    let percent_of_transactions_with_a_segwit_vin_or_vout =
-        transactions_segwit_count_based_on_vins_or_vouts_not_including_coinbase as f64
-            / transactions_count_not_including_coinbase as f64;
-    let percent_based_on_transaction_hexes =
-        transactions_segwit_count_based_on_transaction_hex_not_including_coinbase as f64
-            / transactions_count_not_including_coinbase as f64;
-    // https://transactionfee.info/charts/payments-spending-segwit/
-    let percent_of_payments_spending_segwit_per_day =
-        payments_from_segwit_spending_transactions_count_not_including_coinbase as f64
-            / vouts_count_minus_change_vout as f64;
-    let percent_of_segwit_spending_transactions_per_day =
-        segwit_spending_transactions_count_not_including_coinbase as f64
-            / transactions_count_not_including_coinbase as f64;
-
-    (
-        percent_of_transactions_with_a_segwit_vout,
-        percent_of_transactions_with_a_segwit_vin_or_vout,
-        percent_based_on_transaction_hexes,
-        percent_of_payments_spending_segwit_per_day,
-        percent_of_segwit_spending_transactions_per_day,
-    )
+    let share_of_transactions = |count: u64| SegwitShare {
+        count,
+        total: transactions_count,
+    };
+    let based_on_vins = options.based_on_vouts_and_vins && prevout_error.is_none();
+    SegwitAdoptionReport {
+        options: options.clone(),
+        blocks_count,
+        transactions_count,
+        transactions_with_witness_data: options
+            .based_on_transaction_hex
+            .then(|| share_of_transactions(transactions_with_witness_data_count)),
+        transactions_with_a_segwit_vout: based_on_vouts
+            .then(|| share_of_transactions(transactions_with_a_segwit_vout_count)),
+        output_split: based_on_vouts.then(|| get_segwit_split(&vout_script_types, false)),
+        transactions_with_a_segwit_vin_or_vout: based_on_vins
+            .then(|| share_of_transactions(transactions_with_a_segwit_vin_or_vout_count)),
+        segwit_spending_transactions: based_on_vins
+            .then(|| share_of_transactions(segwit_spending_transactions_count)),
+        payments_spending_segwit: based_on_vins.then_some(SegwitShare {
+            count: payments_spending_segwit_count,
+            total: payments_count,
+        }),
+        spent_input_split: based_on_vins.then(|| get_segwit_split(&spent_script_types, true)),
+        prevout_error,
+    }
 }
@@ -0,0 +1,558 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use jsonrpc::serde_json::value::to_raw_value;
+use serde::Deserialize;
+
+use crate::{btc_to_sats, Client};
+
+// Lower bounds, in sat/vB, of the buckets of the fee rate histogram.
+pub const FEE_RATE_HISTOGRAM_BUCKETS: [f64; 24] = [
+    0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 12.0, 15.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0,
+    80.0, 100.0, 125.0, 150.0, 200.0, 300.0, 500.0,
+];
+// Default -blockmaxweight of bitcoind (3,996,000), which leaves room for the coinbase.
+pub const MAX_BLOCK_VSIZE: u64 = 999_000;
+
+#[derive(Deserialize)]
+struct MempoolEntryFeesResponse {
+    base: f64,
+    modified: f64,
+    ancestor: f64,
+    descendant: f64,
+}
+
+// Not using GetRawMempoolCommand, as its verbose response requires the deprecated fee fields that
+// newer versions of bitcoind no longer return.
+#[derive(Deserialize)]
+struct MempoolEntryResponse {
+    vsize: u64,
+    weight: u64,
+    time: u64,
+    height: u64,
+    descendantcount: u64,
+    descendantsize: u64,
+    ancestorcount: u64,
+    ancestorsize: u64,
+    wtxid: String,
+    fees: MempoolEntryFeesResponse,
+    depends: Vec<String>,
+    spentby: Vec<String>,
+    #[serde(rename = "bip125-replaceable", default)]
+    bip125_replaceable: bool,
+}
+
+#[derive(Deserialize)]
+struct MempoolInfoResponse {
+    loaded: bool,
+    size: u64,
+    bytes: u64,
+    usage: u64,
+    maxmempool: u64,
+    // in btc/kvB
+    mempoolminfee: f64,
+    minrelaytxfee: f64,
+}
+
+// All fees in sats.
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub txid: String,
+    pub wtxid: String,
+    pub vsize: u64,
+    pub weight: u64,
+    pub fee: u64,
+    // fee with the deltas set by prioritisetransaction, used for mining
+    pub modified_fee: u64,
+    // local time the transaction entered the mempool
+    pub time: u64,
+    // block height when the transaction entered the mempool
+    pub height: u64,
+    // ancestor and descendant figures include the transaction itself
+    pub ancestor_count: u64,
+    pub ancestor_vsize: u64,
+    pub ancestor_fees: u64,
+    pub descendant_count: u64,
+    pub descendant_vsize: u64,
+    pub descendant_fees: u64,
+    // unconfirmed parents
+    pub depends: Vec<String>,
+    // unconfirmed children
+    pub spent_by: Vec<String>,
+    pub bip125_replaceable: bool,
+}
+
+impl MempoolEntry {
+    // in sat/vB
+    pub fn fee_rate(&self) -> f64 {
+        self.fee as f64 / self.vsize as f64
+    }
+    // in sat/vB
+    pub fn ancestor_fee_rate(&self) -> f64 {
+        self.ancestor_fees as f64 / self.ancestor_vsize as f64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MempoolInfo {
+    // whether the mempool was fully loaded from disk at startup
+    pub loaded: bool,
+    pub transactions_count: u64,
+    pub vsize: u64,
+    // in bytes
+    pub memory_usage: u64,
+    pub max_memory_usage: u64,
+    // in sat/vB
+    pub mempool_min_fee: f64,
+    pub min_relay_tx_fee: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeRateBucket {
+    // in sat/vB, inclusive
+    pub min_fee_rate: f64,
+    // in sat/vB, exclusive. None for the last bucket.
+    pub max_fee_rate: Option<f64>,
+    pub transactions_count: u64,
+    pub vsize: u64,
+    pub fees: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectedBlock {
+    pub transactions_count: u64,
+    pub vsize: u64,
+    pub fees: u64,
+    // effective fee rates, in sat/vB, of the ancestor packages the transactions were mined with
+    pub min_fee_rate: f64,
+    pub max_fee_rate: f64,
+    pub median_fee_rate: f64,
+    // in the order they'd be mined
+    pub txids: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MempoolReport {
+    pub info: MempoolInfo,
+    pub transactions_count: u64,
+    pub total_vsize: u64,
+    pub total_fees: u64,
+    pub fee_rate_histogram: Vec<FeeRateBucket>,
+    pub projected_blocks: Vec<ProjectedBlock>,
+}
+
+// A transaction along with its ancestors that weren't mined yet.
+struct Package<'a> {
+    // ancestors first
+    txids: Vec<&'a str>,
+    fees: u64,
+    vsize: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PackageScore {
+    fees: u64,
+    vsize: u64,
+}
+
+impl Ord for PackageScore {
+    // Compares the fee rates without dividing.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.fees as u128 * other.vsize as u128).cmp(&(other.fees as u128 * self.vsize as u128))
+    }
+}
+
+impl PartialOrd for PackageScore {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for PackageScore {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for PackageScore {}
+
+fn get_mempool_entry_from_response(txid: String, response: MempoolEntryResponse) -> MempoolEntry {
+    MempoolEntry {
+        txid,
+        wtxid: response.wtxid,
+        vsize: response.vsize,
+        weight: response.weight,
+        fee: btc_to_sats(response.fees.base),
+        modified_fee: btc_to_sats(response.fees.modified),
+        time: response.time,
+        height: response.height,
+        ancestor_count: response.ancestorcount,
+        ancestor_vsize: response.ancestorsize,
+        ancestor_fees: btc_to_sats(response.fees.ancestor),
+        descendant_count: response.descendantcount,
+        descendant_vsize: response.descendantsize,
+        descendant_fees: btc_to_sats(response.fees.descendant),
+        depends: response.depends,
+        spent_by: response.spentby,
+        bip125_replaceable: response.bip125_replaceable,
+    }
+}
+
+// Keyed by txid.
+pub fn get_mempool_entries(client: &Client) -> HashMap<String, MempoolEntry> {
+    let response: HashMap<String, MempoolEntryResponse> = client
+        .call("getrawmempool", &[to_raw_value(&true).unwrap()])
+        .unwrap();
+    response
+        .into_iter()
+        .map(|(txid, entry)| {
+            let entry = get_mempool_entry_from_response(txid.clone(), entry);
+            (txid, entry)
+        })
+        .collect()
+}
+
+// None when the transaction isn't in the mempool.
+pub fn get_mempool_entry(
+    client: &Client,
+    txid: &str,
+) -> Result<Option<MempoolEntry>, jsonrpc::Error> {
+    let response: MempoolEntryResponse =
+        match client.call("getmempoolentry", &[to_raw_value(txid).unwrap()]) {
+            Ok(response) => response,
+            // RPC_INVALID_ADDRESS_OR_KEY, "Transaction not in mempool"
+            Err(jsonrpc::Error::Rpc(rpc_error)) if rpc_error.code == -5 => return Ok(None),
+            Err(error) => return Err(error),
+        };
+    Ok(Some(get_mempool_entry_from_response(
+        txid.to_string(),
+        response,
+    )))
+}
+
+pub fn get_mempool_info(client: &Client) -> MempoolInfo {
+    let response: MempoolInfoResponse = client.call("getmempoolinfo", &[]).unwrap();
+    MempoolInfo {
+        loaded: response.loaded,
+        transactions_count: response.size,
+        vsize: response.bytes,
+        memory_usage: response.usage,
+        max_memory_usage: response.maxmempool,
+        // btc/kvB to sat/vB
+        mempool_min_fee: response.mempoolminfee * 100_000.0,
+        min_relay_tx_fee: response.minrelaytxfee * 100_000.0,
+    }
+}
+
+// Buckets transactions by their own fee rate, ignoring ancestors and descendants.
+pub fn get_fee_rate_histogram(entries: &HashMap<String, MempoolEntry>) -> Vec<FeeRateBucket> {
+    let mut histogram: Vec<FeeRateBucket> = FEE_RATE_HISTOGRAM_BUCKETS
+        .iter()
+        .enumerate()
+        .map(|(i, min_fee_rate)| FeeRateBucket {
+            min_fee_rate: *min_fee_rate,
+            max_fee_rate: FEE_RATE_HISTOGRAM_BUCKETS.get(i + 1).copied(),
+            transactions_count: 0,
+            vsize: 0,
+            fees: 0,
+        })
+        .collect();
+    for entry in entries.values() {
+        let fee_rate = entry.fee_rate();
+        let bucket = histogram
+            .iter_mut()
+            .rev()
+            .find(|bucket| fee_rate >= bucket.min_fee_rate)
+            .unwrap();
+        bucket.transactions_count += 1;
+        bucket.vsize += entry.vsize;
+        bucket.fees += entry.fee;
+    }
+    histogram
+}
+
+fn get_ancestors<'a>(
+    txid: &'a str,
+    entries: &'a HashMap<String, MempoolEntry>,
+    ancestors_by_txid: &mut HashMap<&'a str, HashSet<&'a str>>,
+) -> HashSet<&'a str> {
+    if let Some(ancestors) = ancestors_by_txid.get(txid) {
+        return ancestors.clone();
+    }
+    let mut ancestors = HashSet::new();
+    for parent_txid in &entries[txid].depends {
+        // a parent can leave the mempool between the two RPC calls
+        if let Some((parent_txid, _)) = entries.get_key_value(parent_txid) {
+            ancestors.insert(parent_txid.as_str());
+            ancestors.extend(get_ancestors(parent_txid, entries, ancestors_by_txid));
+        }
+    }
+    ancestors_by_txid.insert(txid, ancestors.clone());
+    ancestors
+}
+
+fn get_descendants<'a>(txid: &str, entries: &'a HashMap<String, MempoolEntry>) -> Vec<&'a str> {
+    let mut descendants = vec![];
+    let mut seen = HashSet::new();
+    let mut to_visit = vec![txid];
+    while let Some(txid) = to_visit.pop() {
+        for child_txid in &entries[txid].spent_by {
+            if let Some((child_txid, _)) = entries.get_key_value(child_txid) {
+                if seen.insert(child_txid.as_str()) {
+                    descendants.push(child_txid.as_str());
+                    to_visit.push(child_txid.as_str());
+                }
+            }
+        }
+    }
+    descendants
+}
+
+fn get_package<'a>(
+    txid: &'a str,
+    entries: &HashMap<String, MempoolEntry>,
+    ancestors_by_txid: &HashMap<&'a str, HashSet<&'a str>>,
+    mined_txids: &HashSet<&str>,
+) -> Package<'a> {
+    let mut txids: Vec<&str> = ancestors_by_txid[txid]
+        .iter()
+        .filter(|ancestor_txid| !mined_txids.contains(*ancestor_txid))
+        .copied()
+        .collect();
+    // ancestors always have fewer ancestors than their descendants
+    txids.sort_by_key(|txid| (ancestors_by_txid[txid].len(), *txid));
+    txids.push(txid);
+    Package {
+        fees: txids.iter().map(|txid| entries[*txid].modified_fee).sum(),
+        vsize: txids.iter().map(|txid| entries[*txid].vsize).sum(),
+        txids,
+    }
+}
+
+// Same idea as bitcoind's block assembler: repeatedly mine the transaction whose package, made of
+// itself and its unmined ancestors, pays the highest fee rate.
+fn get_packages_in_mining_order(entries: &HashMap<String, MempoolEntry>) -> Vec<Package<'_>> {
+    let mut ancestors_by_txid: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for txid in entries.keys() {
+        get_ancestors(txid, entries, &mut ancestors_by_txid);
+    }
+    let mut mined_txids: HashSet<&str> = HashSet::new();
+    let mut heap: BinaryHeap<(PackageScore, &str)> = entries
+        .keys()
+        .map(|txid| {
+            let package = get_package(txid, entries, &ancestors_by_txid, &mined_txids);
+            let score = PackageScore {
+                fees: package.fees,
+                vsize: package.vsize,
+            };
+            (score, txid.as_str())
+        })
+        .collect();
+    let mut packages = vec![];
+    while let Some((score, txid)) = heap.pop() {
+        if mined_txids.contains(txid) {
+            continue;
+        }
+        let package = get_package(txid, entries, &ancestors_by_txid, &mined_txids);
+        let current_score = PackageScore {
+            fees: package.fees,
+            vsize: package.vsize,
+        };
+        // stale entry, the score changed since one of its ancestors got mined
+        if current_score != score {
+            heap.push((current_score, txid));
+            continue;
+        }
+        mined_txids.extend(package.txids.iter().copied());
+        // the packages of the descendants shrank, so their scores need to be updated
+        for mined_txid in &package.txids {
+            for descendant_txid in get_descendants(mined_txid, entries) {
+                if !mined_txids.contains(descendant_txid) {
+                    let descendant_package =
+                        get_package(descendant_txid, entries, &ancestors_by_txid, &mined_txids);
+                    let descendant_score = PackageScore {
+                        fees: descendant_package.fees,
+                        vsize: descendant_package.vsize,
+                    };
+                    heap.push((descendant_score, descendant_txid));
+                }
+            }
+        }
+        packages.push(package);
+    }
+    packages
+}
+
+fn get_projected_block(packages: &[Package]) -> ProjectedBlock {
+    let mut fee_rates: Vec<f64> = packages
+        .iter()
+        .flat_map(|package| {
+            let fee_rate = package.fees as f64 / package.vsize as f64;
+            package.txids.iter().map(move |_| fee_rate)
+        })
+        .collect();
+    fee_rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ProjectedBlock {
+        transactions_count: fee_rates.len() as u64,
+        vsize: packages.iter().map(|package| package.vsize).sum(),
+        fees: packages.iter().map(|package| package.fees).sum(),
+        min_fee_rate: fee_rates[0],
+        max_fee_rate: fee_rates[fee_rates.len() - 1],
+        median_fee_rate: fee_rates[fee_rates.len() / 2],
+        txids: packages
+            .iter()
+            .flat_map(|package| package.txids.iter().map(|txid| txid.to_string()))
+            .collect(),
+    }
+}
+
+// The next `blocks_count` blocks, assuming no new transactions arrive. Packages are mined in order
+// until one doesn't fit, which starts the next block.
+pub fn get_projected_blocks(
+    entries: &HashMap<String, MempoolEntry>,
+    blocks_count: u64,
+) -> Vec<ProjectedBlock> {
+    if blocks_count == 0 {
+        return vec![];
+    }
+    let mut projected_blocks = vec![];
+    let mut block_packages: Vec<Package> = vec![];
+    let mut block_vsize = 0;
+    for package in get_packages_in_mining_order(entries) {
+        if block_vsize + package.vsize > MAX_BLOCK_VSIZE && !block_packages.is_empty() {
+            projected_blocks.push(get_projected_block(&block_packages));
+            block_packages.clear();
+            block_vsize = 0;
+            if projected_blocks.len() as u64 == blocks_count {
+                return projected_blocks;
+            }
+        }
+        block_vsize += package.vsize;
+        block_packages.push(package);
+    }
+    if !block_packages.is_empty() && (projected_blocks.len() as u64) < blocks_count {
+        projected_blocks.push(get_projected_block(&block_packages));
+    }
+    projected_blocks
+}
+
+// takes a long time with a large mempool
+pub fn get_mempool_report(client: &Client, projected_blocks_count: u64) -> MempoolReport {
+    let info = get_mempool_info(client);
+    let entries = get_mempool_entries(client);
+    MempoolReport {
+        info,
+        transactions_count: entries.len() as u64,
+        total_vsize: entries.values().map(|entry| entry.vsize).sum(),
+        total_fees: entries.values().map(|entry| entry.fee).sum(),
+        fee_rate_histogram: get_fee_rate_histogram(&entries),
+        projected_blocks: get_projected_blocks(&entries, projected_blocks_count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(
+        txid: &str,
+        fee: u64,
+        vsize: u64,
+        depends: &[&str],
+        spent_by: &[&str],
+    ) -> MempoolEntry {
+        MempoolEntry {
+            txid: txid.to_string(),
+            wtxid: txid.to_string(),
+            vsize,
+            weight: vsize * 4,
+            fee,
+            modified_fee: fee,
+            time: 0,
+            height: 0,
+            // not used to select the packages, as they're worked out from depends and spent_by
+            ancestor_count: 0,
+            ancestor_vsize: 0,
+            ancestor_fees: 0,
+            descendant_count: 0,
+            descendant_vsize: 0,
+            descendant_fees: 0,
+            depends: depends.iter().map(|txid| txid.to_string()).collect(),
+            spent_by: spent_by.iter().map(|txid| txid.to_string()).collect(),
+            bip125_replaceable: false,
+        }
+    }
+
+    fn entries_by_txid(entries: Vec<MempoolEntry>) -> HashMap<String, MempoolEntry> {
+        entries
+            .into_iter()
+            .map(|entry| (entry.txid.clone(), entry))
+            .collect()
+    }
+
+    #[test]
+    fn child_pays_for_its_parent() {
+        let entries = entries_by_txid(vec![
+            // 1 sat/vB, with a 20 sat/vB child making a 10.5 sat/vB package
+            entry("parent", 100, 100, &[], &["child"]),
+            entry("child", 2_000, 100, &["parent"], &[]),
+            entry("alone", 500, 100, &[], &[]),
+        ]);
+        let projected_blocks = get_projected_blocks(&entries, 1);
+        assert_eq!(projected_blocks.len(), 1);
+        let projected_block = &projected_blocks[0];
+        assert_eq!(projected_block.txids, vec!["parent", "child", "alone"]);
+        assert_eq!(projected_block.transactions_count, 3);
+        assert_eq!(projected_block.vsize, 300);
+        assert_eq!(projected_block.fees, 2_600);
+        assert_eq!(projected_block.min_fee_rate, 5.0);
+        assert_eq!(projected_block.max_fee_rate, 10.5);
+        assert_eq!(projected_block.median_fee_rate, 10.5);
+    }
+
+    #[test]
+    fn children_are_rescored_once_their_parent_is_mined() {
+        let entries = entries_by_txid(vec![
+            // 10 sat/vB parent, then its 1 sat/vB child on its own
+            entry("parent", 1_000, 100, &[], &["child"]),
+            entry("child", 100, 100, &["parent"], &[]),
+            entry("alone", 300, 100, &[], &[]),
+        ]);
+        let projected_blocks = get_projected_blocks(&entries, 1);
+        assert_eq!(projected_blocks[0].txids, vec!["parent", "alone", "child"]);
+        assert_eq!(projected_blocks[0].min_fee_rate, 1.0);
+    }
+
+    #[test]
+    fn ancestors_come_before_their_descendants() {
+        // a chain where only the last transaction pays
+        let entries = entries_by_txid(vec![
+            entry("first", 0, 100, &[], &["second"]),
+            entry("second", 0, 100, &["first"], &["third"]),
+            entry("third", 3_000, 100, &["second"], &[]),
+        ]);
+        let projected_blocks = get_projected_blocks(&entries, 1);
+        assert_eq!(projected_blocks[0].txids, vec!["first", "second", "third"]);
+        assert_eq!(projected_blocks[0].min_fee_rate, 10.0);
+    }
+
+    #[test]
+    fn packages_that_dont_fit_start_the_next_block() {
+        let entries = entries_by_txid(vec![
+            entry("a", 4_000_000, 400_000, &[], &[]),
+            entry("b", 3_000_000, 400_000, &[], &[]),
+            entry("c", 2_000_000, 400_000, &[], &[]),
+        ]);
+        let projected_blocks = get_projected_blocks(&entries, 3);
+        let txids: Vec<Vec<String>> = projected_blocks
+            .iter()
+            .map(|projected_block| projected_block.txids.clone())
+            .collect();
+        assert_eq!(txids, vec![vec!["a", "b"], vec!["c"]]);
+        assert_eq!(projected_blocks[0].vsize, 800_000);
+
+        assert_eq!(get_projected_blocks(&entries, 1).len(), 1);
+        assert!(get_projected_blocks(&entries, 0).is_empty());
+        assert!(get_projected_blocks(&HashMap::new(), 1).is_empty());
+    }
+}
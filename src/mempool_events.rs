@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+use bitcoind_request::command::get_block::{DecodeRawTransactionResponse, Vin};
+use jsonrpc::serde_json::value::to_raw_value;
+use serde::Deserialize;
+
+use crate::mempool::{get_mempool_entry, MempoolEntry};
+use crate::{
+    get_block_height, get_block_with_transactions_at_height, get_transactions_of_block, Client,
+};
+
+// txid and output index
+pub(crate) type Outpoint = (String, u64);
+
+#[derive(Debug, Clone)]
+pub enum MempoolEvent {
+    Added(MempoolEntry),
+    // mined in one of the blocks found since the previous poll
+    Confirmed {
+        txid: String,
+        height: u64,
+        blockhash: String,
+    },
+    // one of its inputs, or one of its unconfirmed ancestors' inputs, got spent by another
+    // transaction, either in the mempool (RBF) or in a block
+    Replaced {
+        txid: String,
+        replaced_by: String,
+    },
+    // evicted because the mempool was full, expired, or removed for any other reason
+    Evicted {
+        txid: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct MempoolTxidsResponse {
+    txids: Vec<String>,
+    mempool_sequence: u64,
+}
+
+fn get_mempool_txids_with_sequence(client: &Client) -> (HashSet<String>, u64) {
+    let response: MempoolTxidsResponse = client
+        .call(
+            "getrawmempool",
+            &[to_raw_value(&false).unwrap(), to_raw_value(&true).unwrap()],
+        )
+        .unwrap();
+    (
+        response.txids.into_iter().collect(),
+        response.mempool_sequence,
+    )
+}
+
+pub(crate) fn get_spent_outpoints(transaction: &DecodeRawTransactionResponse) -> Vec<Outpoint> {
+    transaction
+        .vin
+        .iter()
+        .filter_map(|vin| match vin {
+            Vin::Coinbase(_) => None,
+            Vin::NonCoinbase(vin) => Some((vin.txid.clone(), vin.vout)),
+        })
+        .collect()
+}
+
+// None when the transaction left the mempool. Mempool transactions don't need txindex.
+pub(crate) fn get_spent_outpoints_of_mempool_transaction(
+    client: &Client,
+    txid: &str,
+) -> Option<Vec<Outpoint>> {
+    let transaction: Option<DecodeRawTransactionResponse> = client
+        .call(
+            "getrawtransaction",
+            &[to_raw_value(txid).unwrap(), to_raw_value(&true).unwrap()],
+        )
+        .ok();
+    transaction.map(|transaction| get_spent_outpoints(&transaction))
+}
+
+// Turns the changes of the mempool between two polls into events, so callers don't have to diff
+// snapshots themselves. Polling is cheap while nothing changes, as the mempool sequence number is
+// checked first. Reorgs aren't handled.
+//
+//   let mut stream = MempoolEventStream::new(&client);
+//   loop {
+//       for event in stream.poll(&client) {
+//           println!("{:?}", event);
+//       }
+//       sleep(Duration::from_secs(1));
+//   }
+pub struct MempoolEventStream {
+    pub height: u64,
+    pub mempool_sequence: u64,
+    // outpoints spent by each transaction in the mempool, as they're needed to tell replacements
+    // apart once the transactions are gone
+    spent_outpoints_by_txid: HashMap<String, Vec<Outpoint>>,
+}
+
+impl MempoolEventStream {
+    // Doesn't emit events for the transactions already in the mempool.
+    // takes a long time with a large mempool
+    pub fn new(client: &Client) -> Self {
+        let height = get_block_height(client);
+        let (txids, mempool_sequence) = get_mempool_txids_with_sequence(client);
+        let spent_outpoints_by_txid = txids
+            .into_iter()
+            .filter_map(|txid| {
+                let spent_outpoints = get_spent_outpoints_of_mempool_transaction(client, &txid)?;
+                Some((txid, spent_outpoints))
+            })
+            .collect();
+        MempoolEventStream {
+            height,
+            mempool_sequence,
+            spent_outpoints_by_txid,
+        }
+    }
+
+    // Events of transactions added since the previous poll come first, then the removed ones.
+    // Transactions that were added and removed between two polls are missed.
+    pub fn poll(&mut self, client: &Client) -> Vec<MempoolEvent> {
+        let height = get_block_height(client);
+        let (txids, mempool_sequence) = get_mempool_txids_with_sequence(client);
+        if height == self.height && mempool_sequence == self.mempool_sequence {
+            return vec![];
+        }
+
+        let mut confirmed_txids: HashMap<String, (u64, String)> = HashMap::new();
+        let mut spending_txid_by_outpoint: HashMap<Outpoint, String> = HashMap::new();
+        for height in self.height + 1..=height {
+            let block = get_block_with_transactions_at_height(client, height);
+            for transaction in get_transactions_of_block(block.tx) {
+                for outpoint in get_spent_outpoints(&transaction) {
+                    spending_txid_by_outpoint.insert(outpoint, transaction.txid.clone());
+                }
+                confirmed_txids.insert(transaction.txid, (height, block.hash.clone()));
+            }
+        }
+
+        let mut events = vec![];
+        for txid in &txids {
+            if self.spent_outpoints_by_txid.contains_key(txid) {
+                continue;
+            }
+            // the transaction can leave the mempool while we're fetching it
+            let entry = get_mempool_entry(client, txid).unwrap();
+            let spent_outpoints = get_spent_outpoints_of_mempool_transaction(client, txid);
+            if let (Some(entry), Some(spent_outpoints)) = (entry, spent_outpoints) {
+                for outpoint in &spent_outpoints {
+                    spending_txid_by_outpoint.insert(outpoint.clone(), txid.clone());
+                }
+                self.spent_outpoints_by_txid
+                    .insert(txid.clone(), spent_outpoints);
+                events.push(MempoolEvent::Added(entry));
+            }
+        }
+
+        let removed_txids: Vec<String> = self
+            .spent_outpoints_by_txid
+            .keys()
+            .filter(|txid| !txids.contains(*txid))
+            .cloned()
+            .collect();
+        let removed_spent_outpoints: HashMap<String, Vec<Outpoint>> = removed_txids
+            .into_iter()
+            .map(|txid| {
+                let spent_outpoints = self.spent_outpoints_by_txid.remove(&txid).unwrap();
+                (txid, spent_outpoints)
+            })
+            .collect();
+        let mut replaced_by_by_txid: HashMap<&str, String> = HashMap::new();
+        for (txid, spent_outpoints) in &removed_spent_outpoints {
+            if confirmed_txids.contains_key(txid) {
+                continue;
+            }
+            if let Some(replaced_by) = spent_outpoints
+                .iter()
+                .find_map(|outpoint| spending_txid_by_outpoint.get(outpoint))
+            {
+                replaced_by_by_txid.insert(txid, replaced_by.clone());
+            }
+        }
+        // descendants of replaced transactions are removed along with them
+        loop {
+            let mut replaced_descendants = vec![];
+            for (txid, spent_outpoints) in &removed_spent_outpoints {
+                if confirmed_txids.contains_key(txid)
+                    || replaced_by_by_txid.contains_key(txid.as_str())
+                {
+                    continue;
+                }
+                if let Some(replaced_by) = spent_outpoints
+                    .iter()
+                    .find_map(|(parent_txid, _)| replaced_by_by_txid.get(parent_txid.as_str()))
+                {
+                    replaced_descendants.push((txid.as_str(), replaced_by.clone()));
+                }
+            }
+            if replaced_descendants.is_empty() {
+                break;
+            }
+            replaced_by_by_txid.extend(replaced_descendants);
+        }
+        for txid in removed_spent_outpoints.keys() {
+            let event = if let Some((height, blockhash)) = confirmed_txids.get(txid) {
+                MempoolEvent::Confirmed {
+                    txid: txid.clone(),
+                    height: *height,
+                    blockhash: blockhash.clone(),
+                }
+            } else if let Some(replaced_by) = replaced_by_by_txid.get(txid.as_str()) {
+                MempoolEvent::Replaced {
+                    txid: txid.clone(),
+                    replaced_by: replaced_by.clone(),
+                }
+            } else {
+                MempoolEvent::Evicted { txid: txid.clone() }
+            };
+            events.push(event);
+        }
+
+        self.height = height;
+        self.mempool_sequence = mempool_sequence;
+        events
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use crate::mining_pool::{get_pool_shares, get_pools_for_block_range, BlockPool, PoolShare};
+use crate::{Client, PoolDefinitions};
+
+#[derive(Debug, Clone)]
+pub struct PoolRun {
+    pub pool_name: String,
+    pub start_height: u64,
+    pub end_height: u64,
+    pub blocks_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolLuck {
+    pub pool_name: String,
+    pub blocks_found: u64,
+    // blocks the pool would have found with its estimated share of the hash rate
+    pub expected_blocks: f64,
+    // blocks_found / expected_blocks, where 1.0 means the pool found exactly what was expected
+    pub luck: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MiningCentralizationReport {
+    pub blocks_count: u64,
+    pub pool_shares: Vec<PoolShare>,
+    // between 0.0 and 1.0. Multiply by 10_000 for the conventional (percent squared) scale.
+    pub herfindahl_hirschman_index: f64,
+    // minimum number of pools that together mined more than 50% of the blocks
+    pub nakamoto_coefficient: Option<u64>,
+    pub longest_run: Option<PoolRun>,
+}
+
+fn known_pool_shares(pool_shares: &[PoolShare]) -> impl Iterator<Item = &PoolShare> {
+    pool_shares
+        .iter()
+        .filter(|pool_share| pool_share.pool_name != crate::mining_pool::UNKNOWN_POOL_NAME)
+}
+
+// Blocks from unidentified miners count towards the total, but are not treated as a single pool,
+// since lumping them together would overstate the concentration.
+pub fn get_herfindahl_hirschman_index(pool_shares: &[PoolShare]) -> f64 {
+    known_pool_shares(pool_shares)
+        .map(|pool_share| pool_share.share * pool_share.share)
+        .sum()
+}
+
+// Returns None when the known pools together don't reach a majority.
+pub fn get_nakamoto_coefficient(pool_shares: &[PoolShare]) -> Option<u64> {
+    let mut shares: Vec<f64> = known_pool_shares(pool_shares)
+        .map(|pool_share| pool_share.share)
+        .collect();
+    shares.sort_by(|a, b| b.total_cmp(a));
+    let mut cumulative_share = 0.0;
+    for (i, share) in shares.iter().enumerate() {
+        cumulative_share += share;
+        if cumulative_share > 0.5 {
+            return Some(i as u64 + 1);
+        }
+    }
+    None
+}
+
+// Longest streak of consecutive blocks mined by the same identified pool. Expects the blocks to be
+// ordered by height.
+pub fn get_longest_pool_run(block_pools: &[BlockPool]) -> Option<PoolRun> {
+    let mut longest_run: Option<PoolRun> = None;
+    let mut current_run: Option<PoolRun> = None;
+    for block_pool in block_pools {
+        let pool_name = match &block_pool.pool_name {
+            Some(pool_name) => pool_name,
+            None => {
+                current_run = None;
+                continue;
+            }
+        };
+        current_run = match current_run {
+            Some(mut run)
+                if &run.pool_name == pool_name && run.end_height + 1 == block_pool.height =>
+            {
+                run.end_height = block_pool.height;
+                run.blocks_count += 1;
+                Some(run)
+            }
+            _ => Some(PoolRun {
+                pool_name: pool_name.clone(),
+                start_height: block_pool.height,
+                end_height: block_pool.height,
+                blocks_count: 1,
+            }),
+        };
+        let run = current_run.as_ref().unwrap();
+        let is_longest = match &longest_run {
+            Some(longest) => run.blocks_count > longest.blocks_count,
+            None => true,
+        };
+        if is_longest {
+            longest_run = current_run.clone();
+        }
+    }
+    longest_run
+}
+
+pub fn get_mining_centralization_report(block_pools: &[BlockPool]) -> MiningCentralizationReport {
+    let pool_shares = get_pool_shares(block_pools);
+    MiningCentralizationReport {
+        blocks_count: block_pools.len() as u64,
+        herfindahl_hirschman_index: get_herfindahl_hirschman_index(&pool_shares),
+        nakamoto_coefficient: get_nakamoto_coefficient(&pool_shares),
+        longest_run: get_longest_pool_run(block_pools),
+        pool_shares,
+    }
+}
+
+// takes a long time
+pub fn get_mining_centralization_report_for_block_range(
+    client: &Client,
+    definitions: &PoolDefinitions,
+    heights: RangeInclusive<u64>,
+) -> MiningCentralizationReport {
+    let block_pools = get_pools_for_block_range(client, definitions, heights);
+    get_mining_centralization_report(&block_pools)
+}
+
+// Compares the blocks each pool found in `block_pools` with what it was expected to find given its
+// share in `estimation_block_pools` (usually a longer window, used as a proxy for hash rate share).
+// Sorted by luck, luckiest first.
+pub fn get_pool_luck(
+    estimation_block_pools: &[BlockPool],
+    block_pools: &[BlockPool],
+) -> Vec<PoolLuck> {
+    // no expected blocks to compare with
+    if block_pools.is_empty() {
+        return vec![];
+    }
+    let blocks_count = block_pools.len() as f64;
+    let mut blocks_found_by_pool: HashMap<&str, u64> = HashMap::new();
+    for block_pool in block_pools {
+        *blocks_found_by_pool
+            .entry(block_pool.pool_name_or_unknown())
+            .or_insert(0) += 1;
+    }
+    let mut pool_lucks: Vec<PoolLuck> = known_pool_shares(&get_pool_shares(estimation_block_pools))
+        .map(|pool_share| {
+            let blocks_found = *blocks_found_by_pool
+                .get(pool_share.pool_name.as_str())
+                .unwrap_or(&0);
+            let expected_blocks = pool_share.share * blocks_count;
+            PoolLuck {
+                pool_name: pool_share.pool_name.clone(),
+                blocks_found,
+                expected_blocks,
+                luck: blocks_found as f64 / expected_blocks,
+            }
+        })
+        .collect();
+    pool_lucks.sort_by(|a, b| b.luck.total_cmp(&a.luck));
+    pool_lucks
+}
+
+// takes a long time
+pub fn get_pool_luck_for_block_range(
+    client: &Client,
+    definitions: &PoolDefinitions,
+    estimation_heights: RangeInclusive<u64>,
+    heights: RangeInclusive<u64>,
+) -> Vec<PoolLuck> {
+    // Fetch the union of both ranges once, as the estimation window usually contains the other.
+    // Ranges apart from each other are fetched separately, to skip the blocks between them.
+    let ranges_overlap = !estimation_heights.is_empty()
+        && !heights.is_empty()
+        && *estimation_heights.start() <= heights.end() + 1
+        && *heights.start() <= estimation_heights.end() + 1;
+    let all_block_pools = if ranges_overlap {
+        let start_height = *estimation_heights.start().min(heights.start());
+        let end_height = *estimation_heights.end().max(heights.end());
+        get_pools_for_block_range(client, definitions, start_height..=end_height)
+    } else {
+        let mut block_pools =
+            get_pools_for_block_range(client, definitions, estimation_heights.clone());
+        block_pools.extend(get_pools_for_block_range(
+            client,
+            definitions,
+            heights.clone(),
+        ));
+        block_pools
+    };
+    let block_pools_in = |range: &RangeInclusive<u64>| -> Vec<BlockPool> {
+        all_block_pools
+            .iter()
+            .filter(|block_pool| range.contains(&block_pool.height))
+            .cloned()
+            .collect()
+    };
+    get_pool_luck(
+        &block_pools_in(&estimation_heights),
+        &block_pools_in(&heights),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mining_pool::UNKNOWN_POOL_NAME;
+
+    fn pool_share(pool_name: &str, blocks_count: u64, total_blocks_count: u64) -> PoolShare {
+        PoolShare {
+            pool_name: pool_name.to_string(),
+            blocks_count,
+            share: blocks_count as f64 / total_blocks_count as f64,
+        }
+    }
+
+    fn block_pool(height: u64, pool_name: Option<&str>) -> BlockPool {
+        BlockPool {
+            height,
+            blockhash: String::new(),
+            time: 0,
+            pool_name: pool_name.map(|pool_name| pool_name.to_string()),
+            attribution_method: None,
+            coinbase_tag: String::new(),
+            payout_addresses: vec![],
+        }
+    }
+
+    #[test]
+    fn herfindahl_hirschman_index_leaves_out_unknown_miners() {
+        let pool_shares = [
+            pool_share("Foundry", 5, 10),
+            pool_share("AntPool", 3, 10),
+            pool_share(UNKNOWN_POOL_NAME, 2, 10),
+        ];
+        // 0.5² + 0.3²
+        assert!((get_herfindahl_hirschman_index(&pool_shares) - 0.34).abs() < 1e-9);
+        assert_eq!(get_herfindahl_hirschman_index(&[]), 0.0);
+    }
+
+    #[test]
+    fn nakamoto_coefficient_counts_the_largest_pools_first() {
+        let pool_shares = [
+            pool_share("ViaBTC", 5, 20),
+            pool_share(UNKNOWN_POOL_NAME, 4, 20),
+            pool_share("Foundry", 6, 20),
+            pool_share("AntPool", 5, 20),
+        ];
+        // 0.3 + 0.25
+        assert_eq!(get_nakamoto_coefficient(&pool_shares), Some(2));
+        assert_eq!(
+            get_nakamoto_coefficient(&[pool_share("Foundry", 6, 10)]),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn nakamoto_coefficient_needs_a_majority_of_known_pools() {
+        // exactly half isn't a majority
+        let pool_shares = [
+            pool_share("Foundry", 5, 10),
+            pool_share(UNKNOWN_POOL_NAME, 5, 10),
+        ];
+        assert_eq!(get_nakamoto_coefficient(&pool_shares), None);
+        assert_eq!(get_nakamoto_coefficient(&[]), None);
+    }
+
+    #[test]
+    fn longest_run_is_broken_by_other_pools_unknown_miners_and_missing_heights() {
+        let block_pools = [
+            block_pool(100, Some("Foundry")),
+            block_pool(101, Some("Foundry")),
+            block_pool(102, None),
+            block_pool(103, Some("Foundry")),
+            block_pool(104, Some("AntPool")),
+            block_pool(105, Some("AntPool")),
+            block_pool(106, Some("AntPool")),
+            block_pool(107, Some("Foundry")),
+            block_pool(109, Some("Foundry")),
+        ];
+        let longest_run = get_longest_pool_run(&block_pools).unwrap();
+        assert_eq!(longest_run.pool_name, "AntPool");
+        assert_eq!(
+            (
+                longest_run.start_height,
+                longest_run.end_height,
+                longest_run.blocks_count
+            ),
+            (104, 106, 3)
+        );
+    }
+
+    #[test]
+    fn longest_run_keeps_the_first_of_equal_runs() {
+        let block_pools = [
+            block_pool(100, Some("Foundry")),
+            block_pool(101, Some("Foundry")),
+            block_pool(102, Some("AntPool")),
+            block_pool(103, Some("AntPool")),
+        ];
+        let longest_run = get_longest_pool_run(&block_pools).unwrap();
+        assert_eq!(
+            (longest_run.pool_name.as_str(), longest_run.start_height),
+            ("Foundry", 100)
+        );
+        assert!(get_longest_pool_run(&[block_pool(100, None)]).is_none());
+        assert!(get_longest_pool_run(&[]).is_none());
+    }
+
+    #[test]
+    fn pool_luck_compares_found_blocks_with_the_estimated_share() {
+        let estimation_block_pools = [
+            block_pool(100, Some("Foundry")),
+            block_pool(101, Some("Foundry")),
+            block_pool(102, Some("Foundry")),
+            block_pool(103, Some("AntPool")),
+        ];
+        let block_pools = [
+            block_pool(104, Some("Foundry")),
+            block_pool(105, Some("AntPool")),
+        ];
+        let pool_lucks = get_pool_luck(&estimation_block_pools, &block_pools);
+        let lucks: Vec<(&str, u64, f64, f64)> = pool_lucks
+            .iter()
+            .map(|pool_luck| {
+                (
+                    pool_luck.pool_name.as_str(),
+                    pool_luck.blocks_found,
+                    pool_luck.expected_blocks,
+                    pool_luck.luck,
+                )
+            })
+            .collect();
+        assert_eq!(
+            lucks,
+            vec![("AntPool", 1, 0.5, 2.0), ("Foundry", 1, 1.5, 1.0 / 1.5)]
+        );
+        assert!(get_pool_luck(&estimation_block_pools, &[]).is_empty());
+    }
+}
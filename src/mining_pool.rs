@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+use bitcoind_request::command::get_block::{DecodeRawTransactionResponse, Vin};
+use jsonrpc::serde_json;
+use serde::Deserialize;
+
+use crate::script::{decode_hex, to_printable_ascii};
+use crate::{get_block_with_transactions_at_height, get_transactions_of_block, Client};
+
+pub const UNKNOWN_POOL_NAME: &str = "Unknown";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PoolDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub link: String,
+}
+
+// Pool definitions in the same shape as the public pools.json:
+// {
+//   "coinbase_tags": { "/ViaBTC/": { "name": "ViaBTC", "link": "https://viabtc.com" } },
+//   "payout_addresses": {
+//     "1Hz96k...": { "name": "Binance Pool", "link": "https://pool.binance.com" }
+//   }
+// }
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PoolDefinitions {
+    #[serde(default)]
+    pub coinbase_tags: HashMap<String, PoolDefinition>,
+    #[serde(default)]
+    pub payout_addresses: HashMap<String, PoolDefinition>,
+}
+
+#[derive(Debug)]
+pub enum PoolDefinitionsError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for PoolDefinitionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PoolDefinitionsError::Io(error) => {
+                write!(f, "failed to read pool definitions: {}", error)
+            }
+            PoolDefinitionsError::Json(error) => {
+                write!(f, "failed to parse pool definitions: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PoolDefinitionsError {}
+
+impl PoolDefinitions {
+    pub fn from_json_str(json: &str) -> Result<Self, PoolDefinitionsError> {
+        serde_json::from_str(json).map_err(PoolDefinitionsError::Json)
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, PoolDefinitionsError> {
+        let json = fs::read_to_string(path).map_err(PoolDefinitionsError::Io)?;
+        Self::from_json_str(&json)
+    }
+
+    // Payout addresses are checked first because they are harder to spoof than a coinbase tag.
+    // When several tags are found in the coinbase, the longest one wins so that a generic tag
+    // (e.g. "/BTC/") can't shadow a more specific one.
+    pub fn identify(
+        &self,
+        coinbase_tag: &str,
+        payout_addresses: &[String],
+    ) -> Option<(&PoolDefinition, PoolAttributionMethod)> {
+        let pool_by_payout_address = payout_addresses
+            .iter()
+            .find_map(|address| self.payout_addresses.get(address));
+        if let Some(pool) = pool_by_payout_address {
+            return Some((pool, PoolAttributionMethod::PayoutAddress));
+        }
+        self.coinbase_tags
+            .iter()
+            .filter(|(tag, _)| coinbase_tag.contains(tag.as_str()))
+            .max_by(|(tag_a, _), (tag_b, _)| {
+                tag_a.len().cmp(&tag_b.len()).then_with(|| tag_b.cmp(tag_a))
+            })
+            .map(|(_, pool)| (pool, PoolAttributionMethod::CoinbaseTag))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolAttributionMethod {
+    PayoutAddress,
+    CoinbaseTag,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockPool {
+    pub height: u64,
+    pub blockhash: String,
+    pub time: u64,
+    pub pool_name: Option<String>,
+    pub attribution_method: Option<PoolAttributionMethod>,
+    // printable ascii of the coinbase scriptSig
+    pub coinbase_tag: String,
+    pub payout_addresses: Vec<String>,
+}
+
+impl BlockPool {
+    pub fn pool_name_or_unknown(&self) -> &str {
+        self.pool_name.as_deref().unwrap_or(UNKNOWN_POOL_NAME)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolShare {
+    pub pool_name: String,
+    pub blocks_count: u64,
+    // between 0.0 and 1.0
+    pub share: f64,
+}
+
+pub(crate) fn get_coinbase_script_sig_hex(
+    coinbase_transaction: &DecodeRawTransactionResponse,
+) -> &str {
+    match coinbase_transaction.vin.first().unwrap() {
+        Vin::Coinbase(coinbase_vin) => &coinbase_vin.coinbase,
+        Vin::NonCoinbase(_) => panic!("first transaction of a block must be the coinbase"),
+    }
+}
+
+pub(crate) fn get_block_pool_from_coinbase_transaction(
+    definitions: &PoolDefinitions,
+    height: u64,
+    blockhash: String,
+    time: u64,
+    coinbase_transaction: &DecodeRawTransactionResponse,
+) -> BlockPool {
+    let coinbase_tag = to_printable_ascii(&decode_hex(get_coinbase_script_sig_hex(
+        coinbase_transaction,
+    )));
+    let payout_addresses: Vec<String> = coinbase_transaction
+        .vout
+        .iter()
+        .filter_map(|vout| vout.script_pub_key.address.clone())
+        .collect();
+    let identification = definitions.identify(&coinbase_tag, &payout_addresses);
+    BlockPool {
+        height,
+        blockhash,
+        time,
+        pool_name: identification.map(|(pool, _)| pool.name.clone()),
+        attribution_method: identification.map(|(_, method)| method),
+        coinbase_tag,
+        payout_addresses,
+    }
+}
+
+pub fn get_pool_for_block_at_height(
+    client: &Client,
+    definitions: &PoolDefinitions,
+    height: u64,
+) -> BlockPool {
+    let block = get_block_with_transactions_at_height(client, height);
+    let transactions = get_transactions_of_block(block.tx);
+    get_block_pool_from_coinbase_transaction(
+        definitions,
+        height,
+        block.hash,
+        block.time,
+        transactions.first().unwrap(),
+    )
+}
+
+// takes a long time
+pub fn get_pools_for_block_range(
+    client: &Client,
+    definitions: &PoolDefinitions,
+    heights: RangeInclusive<u64>,
+) -> Vec<BlockPool> {
+    heights
+        .map(|height| get_pool_for_block_at_height(client, definitions, height))
+        .collect()
+}
+
+// Blocks that couldn't be attributed are grouped under "Unknown". Sorted by blocks found, most
+// first.
+pub fn get_pool_shares(block_pools: &[BlockPool]) -> Vec<PoolShare> {
+    let mut blocks_count_by_pool: HashMap<&str, u64> = HashMap::new();
+    for block_pool in block_pools {
+        *blocks_count_by_pool
+            .entry(block_pool.pool_name_or_unknown())
+            .or_insert(0) += 1;
+    }
+    let total_blocks_count = block_pools.len() as f64;
+    let mut pool_shares: Vec<PoolShare> = blocks_count_by_pool
+        .into_iter()
+        .map(|(pool_name, blocks_count)| PoolShare {
+            pool_name: pool_name.to_string(),
+            blocks_count,
+            share: blocks_count as f64 / total_blocks_count,
+        })
+        .collect();
+    pool_shares.sort_by(|a, b| {
+        b.blocks_count
+            .cmp(&a.blocks_count)
+            .then_with(|| a.pool_name.cmp(&b.pool_name))
+    });
+    pool_shares
+}
+
+// takes a long time
+pub fn get_pool_shares_for_block_range(
+    client: &Client,
+    definitions: &PoolDefinitions,
+    heights: RangeInclusive<u64>,
+) -> Vec<PoolShare> {
+    let block_pools = get_pools_for_block_range(client, definitions, heights);
+    get_pool_shares(&block_pools)
+}
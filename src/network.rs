@@ -0,0 +1,62 @@
+use serde::Deserialize;
+
+use crate::Client;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl Network {
+    // From the chain name returned by getblockchaininfo.
+    pub fn from_chain_name(chain: &str) -> Option<Self> {
+        match chain {
+            "main" => Some(Network::Mainnet),
+            "test" => Some(Network::Testnet),
+            "testnet4" => Some(Network::Testnet4),
+            "signet" => Some(Network::Signet),
+            "regtest" => Some(Network::Regtest),
+            _ => None,
+        }
+    }
+    pub fn blocks_per_halving(&self) -> u64 {
+        match self {
+            Network::Regtest => 150,
+            _ => 210_000,
+        }
+    }
+    // Blocks per BIP9 signaling period.
+    pub fn signaling_period(&self) -> u64 {
+        match self {
+            Network::Regtest => 144,
+            _ => 2016,
+        }
+    }
+    // Height from which blocks must start their coinbase scriptSig with their height (BIP34).
+    pub fn bip34_activation_height(&self) -> u64 {
+        match self {
+            Network::Mainnet => 227_931,
+            Network::Testnet => 21_111,
+            // enforced from the first block
+            Network::Testnet4 | Network::Signet | Network::Regtest => 1,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GetBlockchainInfoChainResponse {
+    chain: String,
+}
+
+pub fn get_network(client: &Client) -> Network {
+    // Not using GetBlockchainInfoCommand, as its response requires fields (like softforks) that
+    // newer versions of bitcoind no longer return.
+    let blockchain_info: GetBlockchainInfoChainResponse =
+        client.call("getblockchaininfo", &[]).unwrap();
+    Network::from_chain_name(&blockchain_info.chain)
+        .unwrap_or_else(|| panic!("unknown chain: {}", blockchain_info.chain))
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+use bitcoind_request::command::get_block::Vin;
+use jsonrpc::serde_json;
+use serde::Deserialize;
+
+use crate::script::{decode_hex, parse_script, ScriptInstruction, OP_RETURN};
+use crate::{
+    get_block_heights_over_last_24_hours, get_block_with_transactions_at_height,
+    get_transactions_of_block, Client,
+};
+
+pub const UNKNOWN_PROTOCOL_NAME: &str = "Unknown";
+
+// In bytes, of the data pushed after OP_RETURN.
+pub const PAYLOAD_SIZE_HISTOGRAM_BUCKETS: [u64; 8] = [0, 1, 21, 33, 41, 81, 161, 1001];
+
+// Prefixes are hex encoded, so they can hold any bytes. Protocols are tried in order, so put the
+// most specific ones first. VeriBlock proof-of-proof publications don't start with a fixed marker,
+// so they go by their 80 bytes size, which also catches any other payload filling the standard
+// OP_RETURN limit.
+pub const DEFAULT_OP_RETURN_PROTOCOL_DEFINITIONS: &str = r#"{
+  "protocols": [
+    { "name": "Runes", "script_prefix": "5d", "link": "https://docs.ordinals.com/runes.html" },
+    { "name": "Omni", "payload_prefix": "6f6d6e69", "link": "https://www.omnilayer.org" },
+    { "name": "Stacks", "payload_prefix": "5832", "link": "https://www.stacks.co" },
+    { "name": "Open Assets", "payload_prefix": "4f410100" },
+    { "name": "Colu", "payload_prefix": "4343" },
+    {
+      "name": "Counterparty",
+      "payload_prefix": "434e545250525459",
+      "encrypted_with_first_input_txid": true,
+      "link": "https://counterparty.io"
+    },
+    { "name": "OpenTimestamps", "payload_size": 32, "link": "https://opentimestamps.org" },
+    { "name": "VeriBlock", "payload_size": 80, "link": "https://www.veriblock.org" }
+  ]
+}"#;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OpReturnProtocol {
+    pub name: String,
+    #[serde(default)]
+    pub link: String,
+    // matched against the script right after OP_RETURN, for protocols that start with an opcode
+    #[serde(default)]
+    pub script_prefix: Option<String>,
+    // matched against the data pushed after OP_RETURN
+    #[serde(default)]
+    pub payload_prefix: Option<String>,
+    // for protocols without a prefix, like the 32 bytes commitments of OpenTimestamps calendars or
+    // the 80 bytes publications of VeriBlock. Anything else of that size is attributed to the
+    // protocol too.
+    #[serde(default)]
+    pub payload_size: Option<u64>,
+    // the payload is encrypted with ARC4, keyed with the txid of the first input (Counterparty)
+    #[serde(default)]
+    pub encrypted_with_first_input_txid: bool,
+}
+
+// {
+//   "protocols": [
+//     { "name": "Omni", "payload_prefix": "6f6d6e69", "link": "https://www.omnilayer.org" }
+//   ]
+// }
+#[derive(Deserialize, Debug, Clone)]
+pub struct OpReturnProtocolDefinitions {
+    #[serde(default)]
+    pub protocols: Vec<OpReturnProtocol>,
+}
+
+#[derive(Debug)]
+pub enum OpReturnProtocolDefinitionsError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // a script_prefix or payload_prefix with an odd length or non hex characters
+    InvalidPrefix {
+        protocol_name: String,
+        prefix: String,
+    },
+}
+
+impl fmt::Display for OpReturnProtocolDefinitionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpReturnProtocolDefinitionsError::Io(error) => {
+                write!(
+                    f,
+                    "failed to read OP_RETURN protocol definitions: {}",
+                    error
+                )
+            }
+            OpReturnProtocolDefinitionsError::Json(error) => {
+                write!(
+                    f,
+                    "failed to parse OP_RETURN protocol definitions: {}",
+                    error
+                )
+            }
+            OpReturnProtocolDefinitionsError::InvalidPrefix {
+                protocol_name,
+                prefix,
+            } => {
+                write!(
+                    f,
+                    "failed to parse OP_RETURN protocol definitions: invalid hex prefix \"{}\" \
+                     for {}",
+                    prefix, protocol_name
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for OpReturnProtocolDefinitionsError {}
+
+// The protocols of DEFAULT_OP_RETURN_PROTOCOL_DEFINITIONS.
+impl Default for OpReturnProtocolDefinitions {
+    fn default() -> Self {
+        Self::from_json_str(DEFAULT_OP_RETURN_PROTOCOL_DEFINITIONS).unwrap()
+    }
+}
+
+// ARC4 is symmetric, so this both encrypts and decrypts.
+fn arc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    if key.is_empty() {
+        return data.to_vec();
+    }
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j: u8 = 0;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            byte ^ state[state[i as usize].wrapping_add(state[j as usize]) as usize]
+        })
+        .collect()
+}
+
+// Data pushed after OP_RETURN, opcodes left out. None when the script isn't an OP_RETURN output.
+pub fn get_op_return_payload(script: &[u8]) -> Option<Vec<u8>> {
+    match script {
+        [OP_RETURN, rest @ ..] => Some(match parse_script(rest) {
+            Some(instructions) => instructions
+                .into_iter()
+                .filter_map(|instruction| match instruction {
+                    ScriptInstruction::Push(data) => Some(data),
+                    ScriptInstruction::Op(_) => None,
+                })
+                .flatten()
+                .collect(),
+            // a push running past the end of the script, keep the bytes as they are
+            None => rest.to_vec(),
+        }),
+        _ => None,
+    }
+}
+
+impl OpReturnProtocolDefinitions {
+    // Prefixes are checked here, as decode_hex would silently drop invalid characters.
+    pub fn from_json_str(json: &str) -> Result<Self, OpReturnProtocolDefinitionsError> {
+        let definitions: Self =
+            serde_json::from_str(json).map_err(OpReturnProtocolDefinitionsError::Json)?;
+        for protocol in &definitions.protocols {
+            for prefix in [&protocol.script_prefix, &protocol.payload_prefix]
+                .into_iter()
+                .flatten()
+            {
+                if !prefix.len().is_multiple_of(2)
+                    || !prefix
+                        .chars()
+                        .all(|character| character.is_ascii_hexdigit())
+                {
+                    return Err(OpReturnProtocolDefinitionsError::InvalidPrefix {
+                        protocol_name: protocol.name.clone(),
+                        prefix: prefix.clone(),
+                    });
+                }
+            }
+        }
+        Ok(definitions)
+    }
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, OpReturnProtocolDefinitionsError> {
+        let json = fs::read_to_string(path).map_err(OpReturnProtocolDefinitionsError::Io)?;
+        Self::from_json_str(&json)
+    }
+
+    // First protocol matching the OP_RETURN output. `first_input_txid` is only needed for the
+    // encrypted protocols.
+    pub fn identify(
+        &self,
+        script: &[u8],
+        first_input_txid: Option<&str>,
+    ) -> Option<&OpReturnProtocol> {
+        let payload = get_op_return_payload(script)?;
+        self.protocols.iter().find(|protocol| {
+            let payload = if protocol.encrypted_with_first_input_txid {
+                match first_input_txid {
+                    Some(txid) => arc4(&decode_hex(txid), &payload),
+                    None => return false,
+                }
+            } else {
+                payload.clone()
+            };
+            let matches_script_prefix = match &protocol.script_prefix {
+                Some(prefix) => script[1..].starts_with(&decode_hex(prefix)),
+                None => true,
+            };
+            let matches_payload_prefix = match &protocol.payload_prefix {
+                Some(prefix) => payload.starts_with(&decode_hex(prefix)),
+                None => true,
+            };
+            let matches_payload_size = match protocol.payload_size {
+                Some(payload_size) => payload.len() as u64 == payload_size,
+                None => true,
+            };
+            matches_script_prefix && matches_payload_prefix && matches_payload_size
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PayloadSizeBucket {
+    // in bytes, inclusive
+    pub min_size: u64,
+    // in bytes, exclusive. None for the last bucket.
+    pub max_size: Option<u64>,
+    pub outputs_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OpReturnProtocolUsage {
+    // UNKNOWN_PROTOCOL_NAME for the outputs no protocol matched
+    pub protocol_name: String,
+    pub outputs_count: u64,
+    // in bytes
+    pub total_payload_size: u64,
+    pub average_payload_size: f64,
+    pub max_payload_size: u64,
+    // one per PAYLOAD_SIZE_HISTOGRAM_BUCKETS
+    pub payload_size_histogram: Vec<PayloadSizeBucket>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OpReturnReport {
+    pub blocks_count: u64,
+    // excludes the outputs of coinbase transactions, like the witness commitment
+    pub outputs_count: u64,
+    // sorted by outputs count, most used first
+    pub protocols: Vec<OpReturnProtocolUsage>,
+}
+
+fn get_op_return_protocol_usage(
+    protocol_name: &str,
+    payload_sizes: &[u64],
+) -> OpReturnProtocolUsage {
+    let mut payload_size_histogram: Vec<PayloadSizeBucket> = PAYLOAD_SIZE_HISTOGRAM_BUCKETS
+        .iter()
+        .enumerate()
+        .map(|(i, min_size)| PayloadSizeBucket {
+            min_size: *min_size,
+            max_size: PAYLOAD_SIZE_HISTOGRAM_BUCKETS.get(i + 1).copied(),
+            outputs_count: 0,
+        })
+        .collect();
+    for payload_size in payload_sizes {
+        let bucket = payload_size_histogram
+            .iter_mut()
+            .rev()
+            .find(|bucket| *payload_size >= bucket.min_size)
+            .unwrap();
+        bucket.outputs_count += 1;
+    }
+    let total_payload_size: u64 = payload_sizes.iter().sum();
+    OpReturnProtocolUsage {
+        protocol_name: protocol_name.to_string(),
+        outputs_count: payload_sizes.len() as u64,
+        total_payload_size,
+        average_payload_size: total_payload_size as f64 / payload_sizes.len() as f64,
+        max_payload_size: payload_sizes.iter().copied().max().unwrap_or(0),
+        payload_size_histogram,
+    }
+}
+
+// takes a long time
+pub fn get_op_return_report_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    definitions: &OpReturnProtocolDefinitions,
+) -> OpReturnReport {
+    let mut blocks_count = 0;
+    let mut payload_sizes_by_protocol_name: HashMap<&str, Vec<u64>> = HashMap::new();
+    for height in heights {
+        blocks_count += 1;
+        let block = get_block_with_transactions_at_height(client, height);
+        for transaction in get_transactions_of_block(block.tx).iter().skip(1) {
+            let first_input_txid = match transaction.vin.first() {
+                Some(Vin::NonCoinbase(vin)) => Some(vin.txid.as_str()),
+                _ => None,
+            };
+            for vout in &transaction.vout {
+                let script = decode_hex(&vout.script_pub_key.hex);
+                let payload = match get_op_return_payload(&script) {
+                    Some(payload) => payload,
+                    None => continue,
+                };
+                let protocol_name = definitions
+                    .identify(&script, first_input_txid)
+                    .map(|protocol| protocol.name.as_str())
+                    .unwrap_or(UNKNOWN_PROTOCOL_NAME);
+                payload_sizes_by_protocol_name
+                    .entry(protocol_name)
+                    .or_default()
+                    .push(payload.len() as u64);
+            }
+        }
+    }
+    let mut protocols: Vec<OpReturnProtocolUsage> = payload_sizes_by_protocol_name
+        .iter()
+        .map(|(protocol_name, payload_sizes)| {
+            get_op_return_protocol_usage(protocol_name, payload_sizes)
+        })
+        .collect();
+    protocols.sort_by(|a, b| {
+        b.outputs_count
+            .cmp(&a.outputs_count)
+            .then_with(|| a.protocol_name.cmp(&b.protocol_name))
+    });
+    OpReturnReport {
+        blocks_count,
+        outputs_count: protocols.iter().map(|usage| usage.outputs_count).sum(),
+        protocols,
+    }
+}
+
+// takes a long time
+pub fn get_op_return_report_over_last_24_hours(
+    client: &Client,
+    definitions: &OpReturnProtocolDefinitions,
+) -> OpReturnReport {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_op_return_report_for_block_range(client, heights, definitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::{encode_hex, OP_PUSHDATA1};
+
+    const FIRST_INPUT_TXID: &str =
+        "6b54a6a9f2d1e4b0c3e8f1a7d9b2c5e8f0a1b3c5d7e9f1a3b5c7d9e1f3a5b7c9";
+
+    // OP_RETURN followed by a single push of the payload
+    fn op_return_script(payload: &[u8]) -> Vec<u8> {
+        let mut script = vec![OP_RETURN];
+        if payload.len() > 75 {
+            script.push(OP_PUSHDATA1);
+        }
+        script.push(payload.len() as u8);
+        script.extend(payload);
+        script
+    }
+
+    fn identified_name(script: &[u8], first_input_txid: Option<&str>) -> Option<String> {
+        OpReturnProtocolDefinitions::default()
+            .identify(script, first_input_txid)
+            .map(|protocol| protocol.name.clone())
+    }
+
+    #[test]
+    fn arc4_matches_the_test_vectors() {
+        assert_eq!(
+            encode_hex(&arc4(b"Key", b"Plaintext")),
+            "bbf316e8d940af0ad3"
+        );
+        assert_eq!(encode_hex(&arc4(b"Wiki", b"pedia")), "1021bf0420");
+        assert_eq!(
+            arc4(b"Key", &decode_hex("bbf316e8d940af0ad3")),
+            b"Plaintext"
+        );
+    }
+
+    #[test]
+    fn payload_joins_the_pushes_and_leaves_out_the_opcodes() {
+        // OP_13, then 2 and 1 bytes pushes
+        assert_eq!(
+            get_op_return_payload(&decode_hex("6a5d020102010a")),
+            Some(vec![0x01, 0x02, 0x0a])
+        );
+        assert_eq!(get_op_return_payload(&decode_hex("6a")), Some(vec![]));
+        // a push running past the end of the script
+        assert_eq!(
+            get_op_return_payload(&decode_hex("6a0501")),
+            Some(vec![0x05, 0x01])
+        );
+        assert_eq!(
+            get_op_return_payload(&decode_hex(
+                "76a914333333333333333333333333333333333333333388ac"
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn default_definitions_identify_the_known_protocols() {
+        // runestone: OP_RETURN OP_13 and the data
+        assert_eq!(
+            identified_name(&decode_hex("6a5d0614c0a2330103"), None).as_deref(),
+            Some("Runes")
+        );
+        // Omni simple send of 8 USDT
+        assert_eq!(
+            identified_name(
+                &decode_hex("6a146f6d6e69000000000000001f000000002faf0800"),
+                None
+            )
+            .as_deref(),
+            Some("Omni")
+        );
+        assert_eq!(
+            identified_name(&op_return_script(&[0x11; 32]), None).as_deref(),
+            Some("OpenTimestamps")
+        );
+        assert_eq!(
+            identified_name(&op_return_script(&[0x11; 80]), None).as_deref(),
+            Some("VeriBlock")
+        );
+        assert_eq!(identified_name(&decode_hex("6a0401020304"), None), None);
+        assert_eq!(identified_name(&[], None), None);
+    }
+
+    #[test]
+    fn counterparty_payloads_are_decrypted_with_the_first_input_txid() {
+        let mut data = b"CNTRPRTY".to_vec();
+        data.extend([0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04]);
+        let script = op_return_script(&arc4(&decode_hex(FIRST_INPUT_TXID), &data));
+        assert_eq!(
+            identified_name(&script, Some(FIRST_INPUT_TXID)).as_deref(),
+            Some("Counterparty")
+        );
+        assert_eq!(identified_name(&script, None), None);
+        let other_txid = FIRST_INPUT_TXID.replace('6', "7");
+        assert_eq!(identified_name(&script, Some(&other_txid)), None);
+        // not encrypted
+        assert_eq!(
+            identified_name(&op_return_script(&data), Some(FIRST_INPUT_TXID)),
+            None
+        );
+    }
+
+    #[test]
+    fn first_matching_protocol_wins() {
+        let definitions = OpReturnProtocolDefinitions::from_json_str(
+            r#"{
+              "protocols": [
+                { "name": "Specific", "payload_prefix": "abcd", "payload_size": 4 },
+                { "name": "Prefix", "payload_prefix": "ab" },
+                { "name": "Size", "payload_size": 4 }
+              ]
+            }"#,
+        )
+        .unwrap();
+        let name_of = |payload_hex: &str| {
+            definitions
+                .identify(&op_return_script(&decode_hex(payload_hex)), None)
+                .map(|protocol| protocol.name.as_str())
+        };
+        assert_eq!(name_of("abcdef01"), Some("Specific"));
+        assert_eq!(name_of("abcdef"), Some("Prefix"));
+        assert_eq!(name_of("01020304"), Some("Size"));
+        assert_eq!(name_of("010203"), None);
+    }
+
+    #[test]
+    fn invalid_prefixes_are_rejected() {
+        for prefix in ["6f6", "zz", "0x6f"] {
+            let json = format!(
+                r#"{{ "protocols": [{{ "name": "Broken", "payload_prefix": "{}" }}] }}"#,
+                prefix
+            );
+            match OpReturnProtocolDefinitions::from_json_str(&json) {
+                Err(OpReturnProtocolDefinitionsError::InvalidPrefix {
+                    protocol_name,
+                    prefix: invalid_prefix,
+                }) => {
+                    assert_eq!(protocol_name, "Broken");
+                    assert_eq!(invalid_prefix, prefix);
+                }
+                result => panic!("expected an invalid prefix error, got {:?}", result),
+            }
+        }
+        assert!(matches!(
+            OpReturnProtocolDefinitions::from_json_str(
+                r#"{ "protocols": [{ "name": "Broken", "script_prefix": "5" }] }"#
+            ),
+            Err(OpReturnProtocolDefinitionsError::InvalidPrefix { .. })
+        ));
+        assert!(matches!(
+            OpReturnProtocolDefinitions::from_json_str("{ \"protocols\": "),
+            Err(OpReturnProtocolDefinitionsError::Json(_))
+        ));
+        assert!(
+            OpReturnProtocolDefinitions::from_json_str(DEFAULT_OP_RETURN_PROTOCOL_DEFINITIONS)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use bitcoind_request::command::{
+    get_block::{DecodeRawTransactionResponse, Vin},
+    get_block_hash::GetBlockHashCommand,
+    CallableCommand,
+};
+use jsonrpc::serde_json;
+use jsonrpc::serde_json::value::to_raw_value;
+use serde::Deserialize;
+
+use crate::mempool_events::Outpoint;
+use crate::{btc_to_sats, Client};
+
+// Roughly a day of outputs. Each entry takes around 300 bytes (the txid, kept twice for the
+// eviction order, and the script hex strings), so about 150 MB once full.
+pub const DEFAULT_PREVOUT_CACHE_CAPACITY: usize = 500_000;
+
+// The output spent by an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prevout {
+    // in sats
+    pub value: u64,
+    pub script_pub_key_hex: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PrevoutResolutionStats {
+    // from getblock verbosity 3
+    pub resolved_from_block_count: u64,
+    // outputs of earlier transactions of the scan
+    pub resolved_from_cache_count: u64,
+    pub resolved_from_get_raw_transaction_count: u64,
+}
+
+#[derive(Debug)]
+pub enum PrevoutError {
+    // the previous transaction isn't in the cache, and the node can't look it up without txindex
+    TxindexRequired { txid: String },
+    // the previous transaction has fewer outputs than the input refers to
+    MissingOutput { txid: String, vout: u64 },
+    Rpc(jsonrpc::Error),
+}
+
+impl fmt::Display for PrevoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrevoutError::TxindexRequired { txid } => write!(
+                f,
+                "failed to find the previous transaction {}: getblock verbosity 3 isn't available \
+                 (needs bitcoind 23.0 or later, and undo data on pruned nodes), so restart \
+                 bitcoind with -txindex",
+                txid
+            ),
+            PrevoutError::MissingOutput { txid, vout } => write!(
+                f,
+                "failed to find output {} of the previous transaction {}",
+                vout, txid
+            ),
+            PrevoutError::Rpc(error) => write!(f, "failed to resolve prevouts: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for PrevoutError {}
+
+#[derive(Deserialize)]
+struct ScriptPubKeyResponse {
+    hex: String,
+}
+
+#[derive(Deserialize)]
+struct VoutResponse {
+    // in btc
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pub_key: ScriptPubKeyResponse,
+}
+
+#[derive(Deserialize)]
+struct VinResponse {
+    // missing for the coinbase
+    txid: Option<String>,
+    // missing before verbosity 3
+    prevout: Option<VoutResponse>,
+}
+
+#[derive(Deserialize)]
+struct TransactionVinsResponse {
+    vin: Vec<VinResponse>,
+}
+
+// Transactions are kept as json, to be read both as DecodeRawTransactionResponse and for their
+// prevouts.
+#[derive(Deserialize)]
+struct BlockResponse {
+    hash: String,
+    time: u64,
+    weight: u64,
+    tx: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct RawTransactionResponse {
+    vout: Vec<VoutResponse>,
+}
+
+impl From<&VoutResponse> for Prevout {
+    fn from(vout: &VoutResponse) -> Self {
+        Prevout {
+            value: btc_to_sats(vout.value),
+            script_pub_key_hex: vout.script_pub_key.hex.clone(),
+        }
+    }
+}
+
+// A block and the outputs spent by its transactions, fetched with a single getblock call.
+#[derive(Debug)]
+pub struct BlockWithPrevouts {
+    pub height: u64,
+    pub hash: String,
+    pub time: u64,
+    pub weight: u64,
+    pub transactions: Vec<DecodeRawTransactionResponse>,
+    // One per transaction, in order, one per input. Empty for the coinbase. The error is kept
+    // instead of returned, so callers that don't need the prevouts can still use the block.
+    pub prevouts: Result<Vec<Vec<Prevout>>, PrevoutError>,
+}
+
+impl BlockWithPrevouts {
+    // Panics with the PrevoutError when the prevouts couldn't be resolved.
+    pub fn get_prevouts(&self) -> &[Vec<Prevout>] {
+        match &self.prevouts {
+            Ok(prevouts) => prevouts,
+            Err(error) => panic!("{}", error),
+        }
+    }
+}
+
+// Finds the outputs spent by the inputs of blocks without one getrawtransaction call per input.
+// Fetches the block with getblock verbosity 3, which includes them. On nodes that don't support it,
+// falls back to a cache of the outputs of the transactions seen while scanning (so scan blocks in
+// order), and only then to getrawtransaction, which needs txindex for confirmed transactions.
+//
+//   let mut resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+//   for height in heights {
+//       let block = resolver.get_block_with_prevouts_at_height(&client, height);
+//       let prevouts = block.get_prevouts();
+//   }
+pub struct PrevoutResolver {
+    pub stats: PrevoutResolutionStats,
+    cache_capacity: usize,
+    cache: HashMap<Outpoint, Prevout>,
+    // oldest first, to evict entries once the cache is full
+    cache_order: VecDeque<Outpoint>,
+}
+
+impl PrevoutResolver {
+    pub fn new(cache_capacity: usize) -> Self {
+        PrevoutResolver {
+            stats: PrevoutResolutionStats::default(),
+            cache_capacity,
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+        }
+    }
+
+    fn insert_into_cache(&mut self, outpoint: Outpoint, prevout: Prevout) {
+        if self.cache_capacity == 0 || self.cache.contains_key(&outpoint) {
+            return;
+        }
+        // spent outputs are removed from the cache but not from cache_order
+        while self.cache.len() >= self.cache_capacity {
+            match self.cache_order.pop_front() {
+                Some(oldest_outpoint) => self.cache.remove(&oldest_outpoint),
+                None => break,
+            };
+        }
+        if self.cache_order.len() >= 2 * self.cache_capacity {
+            let cache = &self.cache;
+            self.cache_order
+                .retain(|outpoint| cache.contains_key(outpoint));
+        }
+        self.cache_order.push_back(outpoint.clone());
+        self.cache.insert(outpoint, prevout);
+    }
+
+    // Nodes before 23.0 answer verbosity 3 like verbosity 2, without the prevouts. Pruned nodes
+    // fail on blocks whose undo data is gone, which are then fetched with verbosity 2.
+    fn get_block_response(client: &Client, blockhash: &str) -> BlockResponse {
+        client
+            .call(
+                "getblock",
+                &[to_raw_value(blockhash).unwrap(), to_raw_value(&3).unwrap()],
+            )
+            .or_else(|_| {
+                client.call(
+                    "getblock",
+                    &[to_raw_value(blockhash).unwrap(), to_raw_value(&2).unwrap()],
+                )
+            })
+            .unwrap()
+    }
+
+    // None when any input is missing its prevout.
+    fn get_prevouts_from_block(transactions: &[serde_json::Value]) -> Option<Vec<Vec<Prevout>>> {
+        transactions
+            .iter()
+            .map(|transaction| {
+                let transaction: TransactionVinsResponse =
+                    serde_json::from_value(transaction.clone()).unwrap();
+                transaction
+                    .vin
+                    .iter()
+                    .filter(|vin| vin.txid.is_some())
+                    .map(|vin| vin.prevout.as_ref().map(Prevout::from))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn get_prevout_with_get_raw_transaction(
+        &mut self,
+        client: &Client,
+        outpoint: &Outpoint,
+    ) -> Result<Prevout, PrevoutError> {
+        let (txid, vout) = outpoint;
+        let transaction: RawTransactionResponse = client
+            .call(
+                "getrawtransaction",
+                &[to_raw_value(txid).unwrap(), to_raw_value(&true).unwrap()],
+            )
+            .map_err(|error| match &error {
+                jsonrpc::Error::Rpc(rpc_error) if rpc_error.message.contains("-txindex") => {
+                    PrevoutError::TxindexRequired { txid: txid.clone() }
+                }
+                _ => PrevoutError::Rpc(error),
+            })?;
+        // the other outputs are likely to be spent by the same transaction or one close to it
+        for (index, vout) in transaction.vout.iter().enumerate() {
+            self.insert_into_cache((txid.clone(), index as u64), Prevout::from(vout));
+        }
+        transaction
+            .vout
+            .get(*vout as usize)
+            .map(Prevout::from)
+            .ok_or_else(|| PrevoutError::MissingOutput {
+                txid: txid.clone(),
+                vout: *vout,
+            })
+    }
+
+    fn get_prevouts_with_cache(
+        &mut self,
+        client: &Client,
+        transactions: &[DecodeRawTransactionResponse],
+    ) -> Result<Vec<Vec<Prevout>>, PrevoutError> {
+        let mut block_prevouts = vec![];
+        for transaction in transactions {
+            let mut prevouts = vec![];
+            for vin in &transaction.vin {
+                let outpoint = match vin {
+                    Vin::Coinbase(_) => continue,
+                    Vin::NonCoinbase(vin) => (vin.txid.clone(), vin.vout),
+                };
+                // an output is only spent once, so it won't be needed again
+                let prevout = match self.cache.remove(&outpoint) {
+                    Some(prevout) => {
+                        self.stats.resolved_from_cache_count += 1;
+                        prevout
+                    }
+                    None => {
+                        let prevout =
+                            self.get_prevout_with_get_raw_transaction(client, &outpoint)?;
+                        self.cache.remove(&outpoint);
+                        self.stats.resolved_from_get_raw_transaction_count += 1;
+                        prevout
+                    }
+                };
+                prevouts.push(prevout);
+            }
+            for vout in &transaction.vout {
+                let prevout = Prevout {
+                    value: btc_to_sats(vout.value),
+                    script_pub_key_hex: vout.script_pub_key.hex.clone(),
+                };
+                self.insert_into_cache((transaction.txid.clone(), vout.n as u64), prevout);
+            }
+            block_prevouts.push(prevouts);
+        }
+        Ok(block_prevouts)
+    }
+
+    pub fn get_block_with_prevouts_at_height(
+        &mut self,
+        client: &Client,
+        height: u64,
+    ) -> BlockWithPrevouts {
+        let blockhash = GetBlockHashCommand::new(height)
+            .call(&client.bitcoind_request_client)
+            .unwrap()
+            .0;
+        let block = Self::get_block_response(client, &blockhash.0);
+        let transactions: Vec<DecodeRawTransactionResponse> = block
+            .tx
+            .iter()
+            .map(|transaction| serde_json::from_value(transaction.clone()).unwrap())
+            .collect();
+        let prevouts = match Self::get_prevouts_from_block(&block.tx) {
+            Some(prevouts) => {
+                self.stats.resolved_from_block_count += prevouts
+                    .iter()
+                    .map(|prevouts| prevouts.len() as u64)
+                    .sum::<u64>();
+                Ok(prevouts)
+            }
+            None => self.get_prevouts_with_cache(client, &transactions),
+        };
+        BlockWithPrevouts {
+            height,
+            hash: block.hash,
+            time: block.time,
+            weight: block.weight,
+            transactions,
+            prevouts,
+        }
+    }
+
+    // The prevouts of every transaction of the block, in order, one per input. Empty for the
+    // coinbase.
+    pub fn get_prevouts_of_block_at_height(
+        &mut self,
+        client: &Client,
+        height: u64,
+    ) -> Result<Vec<Vec<Prevout>>, PrevoutError> {
+        self.get_block_with_prevouts_at_height(client, height)
+            .prevouts
+    }
+}
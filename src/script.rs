@@ -0,0 +1,123 @@
+// Helpers for working with the raw, hex-encoded scripts returned by bitcoind.
+
+pub fn decode_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .filter_map(|i| hex.get(i..i + 2))
+        .filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect()
+}
+
+// Keeps only the printable ascii characters of the given bytes. Miners embed their tags as ascii
+// text, surrounded by arbitrary bytes like the block height and extranonce.
+pub fn to_printable_ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+        .map(|byte| *byte as char)
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Hashes are displayed (txids, wtxids, blockhashes) in the reverse of their internal byte order.
+pub fn decode_reversed_hex(hex: &str) -> Vec<u8> {
+    let mut bytes = decode_hex(hex);
+    bytes.reverse();
+    bytes
+}
+
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_1NEGATE: u8 = 0x4f;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_RETURN: u8 = 0x6a;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptInstruction {
+    Push(Vec<u8>),
+    Op(u8),
+}
+
+// Splits a script into pushes and opcodes. Returns None if a push runs past the end of the
+// script, which can happen for non-standard scripts and coinbase scriptSigs.
+pub fn parse_script(script: &[u8]) -> Option<Vec<ScriptInstruction>> {
+    let mut instructions = vec![];
+    let mut i = 0;
+    while i < script.len() {
+        let opcode = script[i];
+        i += 1;
+        let push_length = match opcode {
+            0x01..=0x4b => opcode as usize,
+            OP_PUSHDATA1 => {
+                let length = *script.get(i)? as usize;
+                i += 1;
+                length
+            }
+            OP_PUSHDATA2 => {
+                let length = u16::from_le_bytes([*script.get(i)?, *script.get(i + 1)?]) as usize;
+                i += 2;
+                length
+            }
+            OP_PUSHDATA4 => {
+                let bytes = script.get(i..i + 4)?;
+                i += 4;
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+            }
+            OP_0 => {
+                instructions.push(ScriptInstruction::Push(vec![]));
+                continue;
+            }
+            _ => {
+                instructions.push(ScriptInstruction::Op(opcode));
+                continue;
+            }
+        };
+        let data = script.get(i..i + push_length)?;
+        i += push_length;
+        instructions.push(ScriptInstruction::Push(data.to_vec()));
+    }
+    Some(instructions)
+}
+
+// Decodes a minimally encoded, little-endian script number (CScriptNum).
+pub fn decode_script_number(bytes: &[u8]) -> Option<i64> {
+    if bytes.is_empty() {
+        return Some(0);
+    }
+    if bytes.len() > 8 {
+        return None;
+    }
+    let mut number: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        number |= (*byte as i64) << (8 * i);
+    }
+    let last_byte = bytes[bytes.len() - 1];
+    if last_byte & 0x80 != 0 {
+        number &= !(0x80_i64 << (8 * (bytes.len() - 1)));
+        number = -number;
+    }
+    Some(number)
+}
+
+// Value of an OP_1NEGATE, OP_1..OP_16 opcode.
+pub fn decode_small_number_opcode(opcode: u8) -> Option<i64> {
+    match opcode {
+        OP_1NEGATE => Some(-1),
+        OP_1..=OP_16 => Some((opcode - OP_1 + 1) as i64),
+        _ => None,
+    }
+}
+
+// Scripts longer than this can never be spent.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+// Outputs bitcoind never adds to the UTXO set, as they can provably never be spent.
+pub fn is_provably_unspendable_script(script: &[u8]) -> bool {
+    script.first() == Some(&OP_RETURN) || script.len() > MAX_SCRIPT_SIZE
+}
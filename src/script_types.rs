@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::get_block::Vin;
+
+use crate::prevouts::{PrevoutResolver, DEFAULT_PREVOUT_CACHE_CAPACITY};
+use crate::script::{
+    decode_hex, decode_small_number_opcode, parse_script, ScriptInstruction, OP_0, OP_1, OP_RETURN,
+};
+use crate::{btc_to_sats, get_block_heights_over_last_24_hours, Client};
+
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_DUP: u8 = 0x76;
+const OP_HASH160: u8 = 0xa9;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    // only told apart from the nested segwit types once spent, as the redeem script is hidden
+    // behind a hash until then
+    P2sh,
+    P2shP2wpkh,
+    P2shP2wsh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    BareMultisig,
+    OpReturn,
+    // anything else, including future witness versions
+    NonStandard,
+}
+
+impl ScriptType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScriptType::P2pk => "P2PK",
+            ScriptType::P2pkh => "P2PKH",
+            ScriptType::P2sh => "P2SH",
+            ScriptType::P2shP2wpkh => "P2SH-P2WPKH",
+            ScriptType::P2shP2wsh => "P2SH-P2WSH",
+            ScriptType::P2wpkh => "P2WPKH",
+            ScriptType::P2wsh => "P2WSH",
+            ScriptType::P2tr => "P2TR",
+            ScriptType::BareMultisig => "bare multisig",
+            ScriptType::OpReturn => "OP_RETURN",
+            ScriptType::NonStandard => "non-standard",
+        }
+    }
+    pub fn is_segwit(&self) -> bool {
+        matches!(
+            self,
+            ScriptType::P2shP2wpkh
+                | ScriptType::P2shP2wsh
+                | ScriptType::P2wpkh
+                | ScriptType::P2wsh
+                | ScriptType::P2tr
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptTypeUsage {
+    pub script_type: ScriptType,
+    pub count: u64,
+    // in sats
+    pub value: u64,
+    // between 0.0 and 100.0
+    pub percent_of_count: f64,
+    pub percent_of_value: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScriptTypeBreakdown {
+    pub blocks_count: u64,
+    // ordered by script type
+    pub outputs: Vec<ScriptTypeUsage>,
+    // excludes coinbase inputs
+    pub spent_inputs: Vec<ScriptTypeUsage>,
+}
+
+fn is_public_key(bytes: &[u8]) -> bool {
+    matches!(
+        (bytes.len(), bytes.first()),
+        (33, Some(0x02 | 0x03)) | (65, Some(0x04))
+    )
+}
+
+fn is_bare_multisig(script: &[u8]) -> bool {
+    let instructions = match parse_script(script) {
+        Some(instructions) => instructions,
+        None => return false,
+    };
+    if instructions.len() < 4
+        || instructions.last() != Some(&ScriptInstruction::Op(OP_CHECKMULTISIG))
+    {
+        return false;
+    }
+    let (required, keys_count) = match (&instructions[0], &instructions[instructions.len() - 2]) {
+        (ScriptInstruction::Op(required), ScriptInstruction::Op(keys_count)) => (
+            decode_small_number_opcode(*required),
+            decode_small_number_opcode(*keys_count),
+        ),
+        _ => return false,
+    };
+    let public_keys = &instructions[1..instructions.len() - 2];
+    let all_public_keys = public_keys.iter().all(|instruction| match instruction {
+        ScriptInstruction::Push(data) => is_public_key(data),
+        ScriptInstruction::Op(_) => false,
+    });
+    match (required, keys_count) {
+        (Some(required), Some(keys_count)) => {
+            all_public_keys
+                && required >= 1
+                && required <= keys_count
+                && keys_count == public_keys.len() as i64
+        }
+        _ => false,
+    }
+}
+
+pub fn get_script_type(script_pub_key: &[u8]) -> ScriptType {
+    match script_pub_key {
+        [OP_DUP, OP_HASH160, 0x14, hash @ .., OP_EQUALVERIFY, OP_CHECKSIG] if hash.len() == 20 => {
+            ScriptType::P2pkh
+        }
+        [OP_HASH160, 0x14, hash @ .., OP_EQUAL] if hash.len() == 20 => ScriptType::P2sh,
+        [OP_0, 0x14, hash @ ..] if hash.len() == 20 => ScriptType::P2wpkh,
+        [OP_0, 0x20, hash @ ..] if hash.len() == 32 => ScriptType::P2wsh,
+        [OP_1, 0x20, key @ ..] if key.len() == 32 => ScriptType::P2tr,
+        [OP_RETURN, ..] => ScriptType::OpReturn,
+        [length, key @ .., OP_CHECKSIG] if *length as usize == key.len() && is_public_key(key) => {
+            ScriptType::P2pk
+        }
+        _ if is_bare_multisig(script_pub_key) => ScriptType::BareMultisig,
+        _ => ScriptType::NonStandard,
+    }
+}
+
+// Script type of a spent output. Looks at the redeem script pushed by the scriptSig to tell the
+// nested segwit types apart from other P2SH outputs.
+pub fn get_spent_script_type(script_pub_key: &[u8], script_sig: &[u8]) -> ScriptType {
+    match get_script_type(script_pub_key) {
+        ScriptType::P2sh => match parse_script(script_sig).as_deref() {
+            Some([ScriptInstruction::Push(redeem_script)]) => {
+                match get_script_type(redeem_script) {
+                    ScriptType::P2wpkh => ScriptType::P2shP2wpkh,
+                    ScriptType::P2wsh => ScriptType::P2shP2wsh,
+                    _ => ScriptType::P2sh,
+                }
+            }
+            _ => ScriptType::P2sh,
+        },
+        script_type => script_type,
+    }
+}
+
+fn get_script_type_usages(totals: BTreeMap<ScriptType, (u64, u64)>) -> Vec<ScriptTypeUsage> {
+    let total_count: u64 = totals.values().map(|(count, _)| count).sum();
+    let total_value: u64 = totals.values().map(|(_, value)| value).sum();
+    totals
+        .into_iter()
+        .map(|(script_type, (count, value))| ScriptTypeUsage {
+            script_type,
+            count,
+            value,
+            percent_of_count: count as f64 / total_count as f64 * 100.0,
+            percent_of_value: if total_value == 0 {
+                0.0
+            } else {
+                value as f64 / total_value as f64 * 100.0
+            },
+        })
+        .collect()
+}
+
+// takes a long time
+// Spent outputs are resolved with the PrevoutResolver, and it panics when they can't be.
+pub fn get_script_type_breakdown_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> ScriptTypeBreakdown {
+    let mut blocks_count = 0;
+    // count and value by script type
+    let mut output_totals: BTreeMap<ScriptType, (u64, u64)> = BTreeMap::new();
+    let mut spent_input_totals: BTreeMap<ScriptType, (u64, u64)> = BTreeMap::new();
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    for height in heights {
+        blocks_count += 1;
+        let block = prevout_resolver.get_block_with_prevouts_at_height(client, height);
+        for (transaction, prevouts) in block.transactions.iter().zip(block.get_prevouts()) {
+            for vout in &transaction.vout {
+                let script_type = get_script_type(&decode_hex(&vout.script_pub_key.hex));
+                let totals = output_totals.entry(script_type).or_default();
+                totals.0 += 1;
+                totals.1 += btc_to_sats(vout.value);
+            }
+            let non_coinbase_vins = transaction.vin.iter().filter_map(|vin| match vin {
+                Vin::NonCoinbase(vin) => Some(vin),
+                Vin::Coinbase(_) => None,
+            });
+            for (vin, prevout) in non_coinbase_vins.zip(prevouts) {
+                let script_type = get_spent_script_type(
+                    &decode_hex(&prevout.script_pub_key_hex),
+                    &decode_hex(&vin.script_sig.hex),
+                );
+                let totals = spent_input_totals.entry(script_type).or_default();
+                totals.0 += 1;
+                totals.1 += prevout.value;
+            }
+        }
+    }
+    ScriptTypeBreakdown {
+        blocks_count,
+        outputs: get_script_type_usages(output_totals),
+        spent_inputs: get_script_type_usages(spent_input_totals),
+    }
+}
+
+// takes a long time
+pub fn get_script_type_breakdown_over_last_24_hours(client: &Client) -> ScriptTypeBreakdown {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_script_type_breakdown_for_block_range(client, heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // public key of the output of the genesis block coinbase
+    const UNCOMPRESSED_PUBLIC_KEY_HEX: &str = "04678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5f";
+    const COMPRESSED_PUBLIC_KEY_HEX: &str =
+        "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeeb6357";
+    const HASH_160_HEX: &str = "8280b37df378db99f66f85c95a783a76ac7a6d59";
+    const HASH_256_HEX: &str = "cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+
+    fn script_type_of(script_pub_key_hex: &str) -> ScriptType {
+        get_script_type(&decode_hex(script_pub_key_hex))
+    }
+
+    #[test]
+    fn script_types_of_standard_outputs() {
+        assert_eq!(
+            script_type_of(&format!("41{}ac", UNCOMPRESSED_PUBLIC_KEY_HEX)),
+            ScriptType::P2pk
+        );
+        assert_eq!(
+            script_type_of(&format!("21{}ac", COMPRESSED_PUBLIC_KEY_HEX)),
+            ScriptType::P2pk
+        );
+        assert_eq!(
+            script_type_of(&format!("76a914{}88ac", HASH_160_HEX)),
+            ScriptType::P2pkh
+        );
+        assert_eq!(
+            script_type_of(&format!("a914{}87", HASH_160_HEX)),
+            ScriptType::P2sh
+        );
+        assert_eq!(
+            script_type_of(&format!("0014{}", HASH_160_HEX)),
+            ScriptType::P2wpkh
+        );
+        assert_eq!(
+            script_type_of(&format!("0020{}", HASH_256_HEX)),
+            ScriptType::P2wsh
+        );
+        assert_eq!(
+            script_type_of(&format!("5120{}", HASH_256_HEX)),
+            ScriptType::P2tr
+        );
+        assert_eq!(script_type_of("6a0401020304"), ScriptType::OpReturn);
+        // 1 of 2
+        assert_eq!(
+            script_type_of(&format!(
+                "5121{}41{}52ae",
+                COMPRESSED_PUBLIC_KEY_HEX, UNCOMPRESSED_PUBLIC_KEY_HEX
+            )),
+            ScriptType::BareMultisig
+        );
+    }
+
+    #[test]
+    fn script_types_of_non_standard_outputs() {
+        // witness version 2
+        assert_eq!(
+            script_type_of(&format!("5220{}", HASH_256_HEX)),
+            ScriptType::NonStandard
+        );
+        // P2WPKH with a 19 bytes program
+        assert_eq!(
+            script_type_of(&format!("0013{}", &HASH_160_HEX[2..])),
+            ScriptType::NonStandard
+        );
+        // P2PK with 33 bytes that aren't a public key
+        assert_eq!(
+            script_type_of(&format!("2105{}ac", HASH_256_HEX)),
+            ScriptType::NonStandard
+        );
+        // 3 of 2
+        assert_eq!(
+            script_type_of(&format!(
+                "5321{}41{}52ae",
+                COMPRESSED_PUBLIC_KEY_HEX, UNCOMPRESSED_PUBLIC_KEY_HEX
+            )),
+            ScriptType::NonStandard
+        );
+        assert_eq!(script_type_of(""), ScriptType::NonStandard);
+    }
+
+    #[test]
+    fn spent_p2sh_script_types_come_from_the_redeem_script() {
+        let script_pub_key = decode_hex(&format!("a914{}87", HASH_160_HEX));
+        // pushes 0014<20 bytes>
+        let p2sh_p2wpkh_script_sig = decode_hex(&format!("160014{}", HASH_160_HEX));
+        assert_eq!(
+            get_spent_script_type(&script_pub_key, &p2sh_p2wpkh_script_sig),
+            ScriptType::P2shP2wpkh
+        );
+        // pushes 0020<32 bytes>
+        let p2sh_p2wsh_script_sig = decode_hex(&format!("220020{}", HASH_256_HEX));
+        assert_eq!(
+            get_spent_script_type(&script_pub_key, &p2sh_p2wsh_script_sig),
+            ScriptType::P2shP2wsh
+        );
+        // OP_0, a signature placeholder and a 1 of 1 multisig redeem script
+        let multisig_script_sig =
+            decode_hex(&format!("0001ff255121{}51ae", COMPRESSED_PUBLIC_KEY_HEX));
+        assert_eq!(
+            get_spent_script_type(&script_pub_key, &multisig_script_sig),
+            ScriptType::P2sh
+        );
+        assert_eq!(
+            get_spent_script_type(&script_pub_key, &[]),
+            ScriptType::P2sh
+        );
+    }
+
+    #[test]
+    fn spent_script_types_other_than_p2sh_ignore_the_script_sig() {
+        let script_sig = decode_hex(&format!("160014{}", HASH_160_HEX));
+        assert_eq!(
+            get_spent_script_type(
+                &decode_hex(&format!("76a914{}88ac", HASH_160_HEX)),
+                &script_sig
+            ),
+            ScriptType::P2pkh
+        );
+        assert_eq!(
+            get_spent_script_type(&decode_hex(&format!("5120{}", HASH_256_HEX)), &[]),
+            ScriptType::P2tr
+        );
+    }
+}
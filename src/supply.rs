@@ -0,0 +1,147 @@
+use std::ops::RangeInclusive;
+
+use serde::Deserialize;
+
+use crate::burned::get_burned_value_for_block_at_height;
+use crate::network::{get_network, Network};
+use crate::{btc_to_sats, get_block_height, Client};
+
+const INITIAL_BLOCK_SUBSIDY: u64 = 50 * 100_000_000;
+// The subsidy is shifted right once per halving, so it reaches zero after 33 halvings and
+// bitcoind stops paying any subsidy after 64.
+pub(crate) const MAX_HALVINGS: u64 = 64;
+
+// The genesis coinbase output was never added to the UTXO set.
+const GENESIS_COINBASE_AMOUNT: u64 = 50 * 100_000_000;
+// Blocks 91842 and 91880 have coinbases with the same txids as the ones in blocks 91812 and
+// 91722, overwriting (and destroying) their outputs before BIP30 prevented it. Mainnet only.
+const MAINNET_DUPLICATE_COINBASES_AMOUNT: u64 = 2 * 50 * 100_000_000;
+
+pub fn get_block_subsidy_at_height(network: Network, height: u64) -> u64 {
+    let halvings = height / network.blocks_per_halving();
+    if halvings >= MAX_HALVINGS {
+        return 0;
+    }
+    INITIAL_BLOCK_SUBSIDY >> halvings
+}
+
+// Total subsidy paid by the blocks from the genesis block up to (and including) the given height,
+// regardless of whether it was claimed or can be spent.
+pub fn get_issued_supply_at_height(network: Network, height: u64) -> u64 {
+    let blocks_per_halving = network.blocks_per_halving();
+    let mut issued_supply = 0;
+    let mut era_start_height = 0;
+    while era_start_height <= height {
+        let era_end_height = (era_start_height + blocks_per_halving - 1).min(height);
+        let blocks_in_era = era_end_height - era_start_height + 1;
+        let subsidy = get_block_subsidy_at_height(network, era_start_height);
+        if subsidy == 0 {
+            break;
+        }
+        issued_supply += blocks_in_era * subsidy;
+        era_start_height += blocks_per_halving;
+    }
+    issued_supply
+}
+
+// Computed from the subsidy schedule, so it doesn't need the slow gettxoutsetinfo call used by
+// get_total_money_supply.
+pub fn get_analytic_money_supply(client: &Client) -> u64 {
+    let network = get_network(client);
+    let block_count = get_block_height(client);
+    get_issued_supply_at_height(network, block_count)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SupplyLosses {
+    // rewards (subsidy + fees) miners were allowed to claim but didn't
+    pub unclaimed_rewards: u64,
+    // value of outputs that can never be spent (OP_RETURN and oversized scripts)
+    pub provably_unspendable: u64,
+}
+
+// takes a very long time
+pub fn get_supply_losses_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> SupplyLosses {
+    let mut supply_losses = SupplyLosses::default();
+    for height in heights {
+        // Burn addresses don't matter here, as their outputs are still in the UTXO set.
+        let burned_value = get_burned_value_for_block_at_height(client, height, &[]);
+        supply_losses.unclaimed_rewards += burned_value.unclaimed_rewards;
+        supply_losses.provably_unspendable +=
+            burned_value.op_return + burned_value.unspendable_scripts;
+    }
+    supply_losses
+}
+
+#[derive(Deserialize)]
+struct GetTxOutSetInfoTotalAmountResponse {
+    height: u64,
+    total_amount: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MoneySupplyReconciliation {
+    pub height: u64,
+    pub issued_supply: u64,
+    // total_amount reported by gettxoutsetinfo
+    pub utxo_set_supply: u64,
+    pub genesis_coinbase: u64,
+    pub duplicate_coinbases: u64,
+    pub unclaimed_rewards: u64,
+    pub provably_unspendable: u64,
+    // difference between the two supplies that isn't explained by the items above. Should be 0.
+    pub unexplained_difference: i64,
+}
+
+pub fn reconcile_money_supply(
+    network: Network,
+    height: u64,
+    utxo_set_supply: u64,
+    supply_losses: &SupplyLosses,
+) -> MoneySupplyReconciliation {
+    let issued_supply = get_issued_supply_at_height(network, height);
+    let duplicate_coinbases = match network {
+        Network::Mainnet => MAINNET_DUPLICATE_COINBASES_AMOUNT,
+        _ => 0,
+    };
+    let explained_difference = GENESIS_COINBASE_AMOUNT
+        + duplicate_coinbases
+        + supply_losses.unclaimed_rewards
+        + supply_losses.provably_unspendable;
+    MoneySupplyReconciliation {
+        height,
+        issued_supply,
+        utxo_set_supply,
+        genesis_coinbase: GENESIS_COINBASE_AMOUNT,
+        duplicate_coinbases,
+        unclaimed_rewards: supply_losses.unclaimed_rewards,
+        provably_unspendable: supply_losses.provably_unspendable,
+        unexplained_difference: issued_supply as i64
+            - utxo_set_supply as i64
+            - explained_difference as i64,
+    }
+}
+
+// Compares the analytic supply with the one in the UTXO set, scanning the whole chain to itemize
+// the difference. Uses gettxoutsetinfo without hashing the UTXO set, which is much faster than
+// the call in get_total_money_supply.
+// takes a very long time
+pub fn get_money_supply_reconciliation(client: &Client) -> MoneySupplyReconciliation {
+    let network = get_network(client);
+    let tx_out_set_info: GetTxOutSetInfoTotalAmountResponse = client
+        .call(
+            "gettxoutsetinfo",
+            &[jsonrpc::serde_json::value::to_raw_value("none").unwrap()],
+        )
+        .unwrap();
+    let supply_losses = get_supply_losses_for_block_range(client, 0..=tx_out_set_info.height);
+    reconcile_money_supply(
+        network,
+        tx_out_set_info.height,
+        btc_to_sats(tx_out_set_info.total_amount),
+        &supply_losses,
+    )
+}
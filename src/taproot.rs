@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::get_block::Vin;
+
+use crate::prevouts::{PrevoutResolver, DEFAULT_PREVOUT_CACHE_CAPACITY};
+use crate::script::decode_hex;
+use crate::script_types::{get_script_type, ScriptType};
+use crate::{get_block_heights_over_last_24_hours, Client};
+
+const ANNEX_TAG: u8 = 0x50;
+const TAPSCRIPT_LEAF_VERSION: u8 = 0xc0;
+const CONTROL_BLOCK_BASE_SIZE: usize = 33;
+const CONTROL_BLOCK_NODE_SIZE: usize = 32;
+const SECONDS_IN_A_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaprootSpendPath {
+    KeyPath,
+    ScriptPath {
+        // number of hashes in the merkle path to the leaf, 0 when the tree is a single leaf
+        control_block_depth: u8,
+        // 0xc0 for tapscript, anything else is a leaf version not defined yet
+        leaf_version: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaprootSpend {
+    pub spend_path: TaprootSpendPath,
+    pub has_annex: bool,
+    // Signatures are told apart by their size (64 bytes, or 65 with a sighash type), so for
+    // script path spends this counts any 64 or 65 bytes stack element, and leaves out the empty
+    // ones standing for missing signatures.
+    pub schnorr_signatures_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaprootDailyUsage {
+    // unix timestamp of the start of the day (UTC)
+    pub start_time: u64,
+    pub blocks_count: u64,
+    pub p2tr_spends_count: u64,
+    pub key_path_spends_count: u64,
+    pub script_path_spends_count: u64,
+    pub spends_with_annex_count: u64,
+    pub schnorr_signatures_count: u64,
+    // script path spends by control block depth
+    pub script_path_spends_by_depth: BTreeMap<u8, u64>,
+    // script path spends by leaf version
+    pub script_path_spends_by_leaf_version: BTreeMap<u8, u64>,
+    // between 0.0 and 100.0
+    pub percent_of_key_path_spends: f64,
+}
+
+impl TaprootDailyUsage {
+    fn new(start_time: u64) -> Self {
+        TaprootDailyUsage {
+            start_time,
+            blocks_count: 0,
+            p2tr_spends_count: 0,
+            key_path_spends_count: 0,
+            script_path_spends_count: 0,
+            spends_with_annex_count: 0,
+            schnorr_signatures_count: 0,
+            script_path_spends_by_depth: BTreeMap::new(),
+            script_path_spends_by_leaf_version: BTreeMap::new(),
+            percent_of_key_path_spends: 0.0,
+        }
+    }
+}
+
+fn is_schnorr_signature(stack_element: &[u8]) -> bool {
+    stack_element.len() == 64 || stack_element.len() == 65
+}
+
+// The witness stack without the annex, and whether there was one.
+fn strip_annex(witness: &[Vec<u8>]) -> (&[Vec<u8>], bool) {
+    match witness {
+        [stack @ .., annex] if !stack.is_empty() && annex.first() == Some(&ANNEX_TAG) => {
+            (stack, true)
+        }
+        _ => (witness, false),
+    }
+}
+
+// Script of the leaf spent by a script path spend, None for key path spends.
+pub(crate) fn get_taproot_leaf_script(witness: &[Vec<u8>]) -> Option<&[u8]> {
+    match get_taproot_spend(witness)?.spend_path {
+        TaprootSpendPath::KeyPath => None,
+        TaprootSpendPath::ScriptPath { .. } => {
+            let (stack, _) = strip_annex(witness);
+            Some(&stack[stack.len() - 2])
+        }
+    }
+}
+
+// Follows the witness rules of BIP341. None when the witness can't be the one of a P2TR spend,
+// like an empty witness or a malformed control block.
+pub fn get_taproot_spend(witness: &[Vec<u8>]) -> Option<TaprootSpend> {
+    let (stack, has_annex) = strip_annex(witness);
+    match stack {
+        [] => None,
+        [signature] => Some(TaprootSpend {
+            spend_path: TaprootSpendPath::KeyPath,
+            has_annex,
+            schnorr_signatures_count: is_schnorr_signature(signature) as u64,
+        }),
+        [script_inputs @ .., _script, control_block] => {
+            if control_block.len() < CONTROL_BLOCK_BASE_SIZE
+                || !(control_block.len() - CONTROL_BLOCK_BASE_SIZE)
+                    .is_multiple_of(CONTROL_BLOCK_NODE_SIZE)
+            {
+                return None;
+            }
+            let leaf_version = control_block[0] & 0xfe;
+            let schnorr_signatures_count = if leaf_version == TAPSCRIPT_LEAF_VERSION {
+                script_inputs
+                    .iter()
+                    .filter(|stack_element| is_schnorr_signature(stack_element))
+                    .count() as u64
+            } else {
+                0
+            };
+            Some(TaprootSpend {
+                spend_path: TaprootSpendPath::ScriptPath {
+                    control_block_depth: ((control_block.len() - CONTROL_BLOCK_BASE_SIZE)
+                        / CONTROL_BLOCK_NODE_SIZE) as u8,
+                    leaf_version,
+                },
+                has_annex,
+                schnorr_signatures_count,
+            })
+        }
+    }
+}
+
+// takes a long time
+// Spent outputs are resolved with the PrevoutResolver, and it panics when they can't be. Ordered by
+// day.
+pub fn get_taproot_usage_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+) -> Vec<TaprootDailyUsage> {
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    let mut usages: BTreeMap<u64, TaprootDailyUsage> = BTreeMap::new();
+    for height in heights {
+        let block = prevout_resolver.get_block_with_prevouts_at_height(client, height);
+        let block_prevouts = block.get_prevouts();
+        let start_time = block.time - block.time % SECONDS_IN_A_DAY;
+        let usage = usages
+            .entry(start_time)
+            .or_insert_with(|| TaprootDailyUsage::new(start_time));
+        usage.blocks_count += 1;
+        for (transaction, prevouts) in block.transactions.iter().zip(block_prevouts) {
+            let non_coinbase_vins = transaction.vin.iter().filter_map(|vin| match vin {
+                Vin::NonCoinbase(vin) => Some(vin),
+                Vin::Coinbase(_) => None,
+            });
+            for (vin, prevout) in non_coinbase_vins.zip(prevouts) {
+                if get_script_type(&decode_hex(&prevout.script_pub_key_hex)) != ScriptType::P2tr {
+                    continue;
+                }
+                let witness: Vec<Vec<u8>> = match &vin.txinwitness {
+                    Some(witness) => witness
+                        .iter()
+                        .map(|stack_element| decode_hex(&stack_element.0))
+                        .collect(),
+                    None => continue,
+                };
+                let spend = match get_taproot_spend(&witness) {
+                    Some(spend) => spend,
+                    None => continue,
+                };
+                usage.p2tr_spends_count += 1;
+                usage.schnorr_signatures_count += spend.schnorr_signatures_count;
+                if spend.has_annex {
+                    usage.spends_with_annex_count += 1;
+                }
+                match spend.spend_path {
+                    TaprootSpendPath::KeyPath => usage.key_path_spends_count += 1,
+                    TaprootSpendPath::ScriptPath {
+                        control_block_depth,
+                        leaf_version,
+                    } => {
+                        usage.script_path_spends_count += 1;
+                        *usage
+                            .script_path_spends_by_depth
+                            .entry(control_block_depth)
+                            .or_default() += 1;
+                        *usage
+                            .script_path_spends_by_leaf_version
+                            .entry(leaf_version)
+                            .or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+    usages
+        .into_values()
+        .map(|mut usage| {
+            if usage.p2tr_spends_count > 0 {
+                usage.percent_of_key_path_spends =
+                    usage.key_path_spends_count as f64 / usage.p2tr_spends_count as f64 * 100.0;
+            }
+            usage
+        })
+        .collect()
+}
+
+// takes a long time
+pub fn get_taproot_usage_over_last_24_hours(client: &Client) -> Vec<TaprootDailyUsage> {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_taproot_usage_for_block_range(client, heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // leaf script: <32 bytes x-only key> OP_CHECKSIG
+    fn leaf_script() -> Vec<u8> {
+        let mut script = vec![0x20];
+        script.extend([0xab; 32]);
+        script.push(0xac);
+        script
+    }
+
+    fn control_block(leaf_version_and_parity: u8, depth: usize) -> Vec<u8> {
+        let mut control_block = vec![leaf_version_and_parity];
+        control_block.extend([0xcd; 32]);
+        control_block.extend(vec![0xef; depth * 32]);
+        control_block
+    }
+
+    #[test]
+    fn key_path_spend() {
+        assert_eq!(
+            get_taproot_spend(&[vec![0x01; 64]]),
+            Some(TaprootSpend {
+                spend_path: TaprootSpendPath::KeyPath,
+                has_annex: false,
+                schnorr_signatures_count: 1,
+            })
+        );
+        // with a sighash type
+        assert_eq!(
+            get_taproot_spend(&[vec![0x01; 65]]).map(|spend| spend.schnorr_signatures_count),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn annex_is_the_last_element_starting_with_0x50_when_there_are_at_least_two() {
+        let witness = [vec![0x01; 64], vec![ANNEX_TAG, 0x00]];
+        assert_eq!(strip_annex(&witness), (&witness[..1], true));
+        assert_eq!(
+            get_taproot_spend(&witness),
+            Some(TaprootSpend {
+                spend_path: TaprootSpendPath::KeyPath,
+                has_annex: true,
+                schnorr_signatures_count: 1,
+            })
+        );
+        // a single element is the signature, whatever its first byte
+        let witness = [vec![ANNEX_TAG; 64]];
+        assert_eq!(strip_annex(&witness), (&witness[..], false));
+        // an element starting with 0x50 before the last one isn't an annex
+        let witness = [vec![ANNEX_TAG], vec![0x01; 64]];
+        assert_eq!(strip_annex(&witness), (&witness[..], false));
+    }
+
+    #[test]
+    fn script_path_spend_of_a_single_leaf_tree() {
+        let witness = [vec![0x01; 64], leaf_script(), control_block(0xc1, 0)];
+        assert_eq!(
+            get_taproot_spend(&witness),
+            Some(TaprootSpend {
+                spend_path: TaprootSpendPath::ScriptPath {
+                    control_block_depth: 0,
+                    leaf_version: TAPSCRIPT_LEAF_VERSION,
+                },
+                has_annex: false,
+                schnorr_signatures_count: 1,
+            })
+        );
+        assert_eq!(get_taproot_leaf_script(&witness), Some(&leaf_script()[..]));
+    }
+
+    #[test]
+    fn script_path_spend_depth_is_the_number_of_merkle_path_hashes() {
+        // a missing signature of a 2 of 2, then an annex
+        let witness = [
+            vec![],
+            vec![0x01; 65],
+            leaf_script(),
+            control_block(0xc0, 3),
+            vec![ANNEX_TAG],
+        ];
+        assert_eq!(
+            get_taproot_spend(&witness),
+            Some(TaprootSpend {
+                spend_path: TaprootSpendPath::ScriptPath {
+                    control_block_depth: 3,
+                    leaf_version: TAPSCRIPT_LEAF_VERSION,
+                },
+                has_annex: true,
+                schnorr_signatures_count: 1,
+            })
+        );
+        assert_eq!(get_taproot_leaf_script(&witness), Some(&leaf_script()[..]));
+    }
+
+    #[test]
+    fn signatures_of_unknown_leaf_versions_are_not_counted() {
+        let witness = [vec![0x01; 64], leaf_script(), control_block(0xc2, 1)];
+        assert_eq!(
+            get_taproot_spend(&witness),
+            Some(TaprootSpend {
+                spend_path: TaprootSpendPath::ScriptPath {
+                    control_block_depth: 1,
+                    leaf_version: 0xc2,
+                },
+                has_annex: false,
+                schnorr_signatures_count: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_witnesses_are_not_taproot_spends() {
+        assert_eq!(get_taproot_spend(&[]), None);
+        // control blocks shorter than 33 bytes or not a multiple of 32 bytes longer
+        let mut control_block = control_block(0xc0, 1);
+        control_block.pop();
+        assert_eq!(get_taproot_spend(&[leaf_script(), control_block]), None);
+        assert_eq!(get_taproot_spend(&[leaf_script(), vec![0xc0; 32]]), None);
+        assert_eq!(get_taproot_leaf_script(&[vec![0x01; 64]]), None);
+    }
+}
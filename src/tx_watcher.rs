@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::thread::sleep;
+use std::time::Duration;
+
+use jsonrpc::serde_json::{json, value::to_raw_value};
+use serde::Deserialize;
+
+use crate::mempool::{get_mempool_entry, MempoolEntry};
+use crate::mempool_events::{
+    get_spent_outpoints, get_spent_outpoints_of_mempool_transaction, Outpoint,
+};
+use crate::{
+    get_block_height, get_block_with_transactions_at_height, get_timestamp_of_block_at_height,
+    get_transactions_of_block, Client,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxStatus {
+    // not found in the mempool nor in any block since it's been watched
+    Unseen,
+    InMempool,
+    Confirmed {
+        height: u64,
+        blockhash: String,
+        // block timestamp
+        time: u64,
+    },
+    // None when the conflicting transaction is unknown
+    Replaced {
+        replaced_by: Option<String>,
+    },
+    // left the mempool without being mined or replaced, like when evicted or expired
+    Dropped,
+    // its block is no longer part of the best chain
+    ReorgedOut,
+}
+
+#[derive(Debug, Clone)]
+pub struct TxStatusTransition {
+    pub txid: String,
+    pub previous_status: TxStatus,
+    pub status: TxStatus,
+    pub confirmations: u64,
+    pub target_confirmations: u64,
+    // local time of the poll that saw the transition
+    pub time: i64,
+    // seconds between entering our mempool and the first confirmation, or now while unconfirmed.
+    // None when it was never seen in our mempool.
+    pub time_in_mempool: Option<i64>,
+    // seconds between entering our mempool and reaching the target confirmations
+    pub time_to_confirm: Option<i64>,
+}
+
+impl TxStatusTransition {
+    pub fn reached_target_confirmations(&self) -> bool {
+        self.confirmations >= self.target_confirmations
+    }
+}
+
+type TxStatusCallback = Box<dyn FnMut(&TxStatusTransition)>;
+
+struct WatchedTransaction {
+    target_confirmations: u64,
+    status: TxStatus,
+    // local time the transaction entered our mempool
+    first_seen_time: Option<i64>,
+    // needed to find what replaced it once it's gone from the mempool
+    spent_outpoints: Vec<Outpoint>,
+    reached_target_confirmations: bool,
+}
+
+#[derive(Deserialize)]
+struct BlockHeaderResponse {
+    height: u64,
+    time: u64,
+    // -1 when the block isn't part of the best chain
+    confirmations: i64,
+}
+
+#[derive(Deserialize)]
+struct TransactionBlockhashResponse {
+    // missing while unconfirmed
+    blockhash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SpendingPrevoutResponse {
+    spendingtxid: Option<String>,
+}
+
+fn get_block_header(client: &Client, blockhash: &str) -> BlockHeaderResponse {
+    client
+        .call("getblockheader", &[to_raw_value(blockhash).unwrap()])
+        .unwrap()
+}
+
+// Only finds confirmed transactions when the node runs with txindex.
+fn get_confirmed_status_with_txindex(client: &Client, txid: &str) -> Option<TxStatus> {
+    let transaction: TransactionBlockhashResponse = client
+        .call(
+            "getrawtransaction",
+            &[to_raw_value(txid).unwrap(), to_raw_value(&true).unwrap()],
+        )
+        .ok()?;
+    let blockhash = transaction.blockhash?;
+    let block_header = get_block_header(client, &blockhash);
+    Some(TxStatus::Confirmed {
+        height: block_header.height,
+        blockhash,
+        time: block_header.time,
+    })
+}
+
+// Mempool transaction spending any of the outpoints. Needs bitcoind 24.0 or later.
+fn get_mempool_spending_txid(client: &Client, outpoints: &[Outpoint]) -> Option<String> {
+    if outpoints.is_empty() {
+        return None;
+    }
+    let outpoints: Vec<_> = outpoints
+        .iter()
+        .map(|(txid, vout)| json!({ "txid": txid, "vout": vout }))
+        .collect();
+    let response: Vec<SpendingPrevoutResponse> = client
+        .call("gettxspendingprevout", &[to_raw_value(&outpoints).unwrap()])
+        .ok()?;
+    response
+        .into_iter()
+        .find_map(|spending_prevout| spending_prevout.spendingtxid)
+}
+
+// Follows transactions until they're confirmed, replaced or dropped.
+//
+//   let mut watcher = TxWatcher::new();
+//   watcher.watch("<txid>", Some(6));
+//   watcher.set_callback(|transition| println!("{:?}", transition));
+//   watcher.watch_until_settled(&client, Duration::from_secs(10)).unwrap();
+//
+// or call `poll` yourself and go through the transitions it returns.
+pub struct TxWatcher {
+    watched_transactions: BTreeMap<String, WatchedTransaction>,
+    // height of the last block looked through
+    scanned_height: Option<u64>,
+    callback: Option<TxStatusCallback>,
+}
+
+impl Default for TxWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TxWatcher {
+    pub fn new() -> Self {
+        TxWatcher {
+            watched_transactions: BTreeMap::new(),
+            scanned_height: None,
+            callback: None,
+        }
+    }
+
+    // Called with every transition, from `poll`.
+    pub fn set_callback<F: FnMut(&TxStatusTransition) + 'static>(&mut self, callback: F) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    // Defaults to 1 confirmation.
+    pub fn watch(&mut self, txid: &str, target_confirmations: Option<u64>) {
+        self.watched_transactions.insert(
+            txid.to_string(),
+            WatchedTransaction {
+                target_confirmations: target_confirmations.unwrap_or(1),
+                status: TxStatus::Unseen,
+                first_seen_time: None,
+                spent_outpoints: vec![],
+                reached_target_confirmations: false,
+            },
+        );
+    }
+
+    pub fn unwatch(&mut self, txid: &str) {
+        self.watched_transactions.remove(txid);
+    }
+
+    pub fn status(&self, txid: &str) -> Option<&TxStatus> {
+        self.watched_transactions
+            .get(txid)
+            .map(|watched_transaction| &watched_transaction.status)
+    }
+
+    // True once every transaction reached its target confirmations, or got replaced or dropped.
+    pub fn is_settled(&self) -> bool {
+        self.watched_transactions
+            .values()
+            .all(|watched_transaction| match watched_transaction.status {
+                TxStatus::Confirmed { .. } => watched_transaction.reached_target_confirmations,
+                TxStatus::Replaced { .. } | TxStatus::Dropped => true,
+                _ => false,
+            })
+    }
+
+    pub fn poll(&mut self, client: &Client) -> Result<Vec<TxStatusTransition>, jsonrpc::Error> {
+        let now = chrono::offset::Utc::now().timestamp();
+        let tip_height = get_block_height(client);
+        let mut scan_from_height = match self.scanned_height {
+            Some(scanned_height) => scanned_height + 1,
+            None => tip_height,
+        };
+
+        let mut reorged_out_txids: HashSet<String> = HashSet::new();
+        for (txid, watched_transaction) in &self.watched_transactions {
+            if let TxStatus::Confirmed {
+                height, blockhash, ..
+            } = &watched_transaction.status
+            {
+                if get_block_header(client, blockhash).confirmations < 0 {
+                    reorged_out_txids.insert(txid.clone());
+                    scan_from_height = scan_from_height.min(*height);
+                }
+            }
+        }
+
+        // blocks mined since the previous poll, and the ones that replaced reorged out blocks
+        let watched_outpoints: HashSet<&Outpoint> = self
+            .watched_transactions
+            .values()
+            .flat_map(|watched_transaction| watched_transaction.spent_outpoints.iter())
+            .collect();
+        let mut confirmed_statuses: HashMap<String, TxStatus> = HashMap::new();
+        let mut spending_txid_by_outpoint: HashMap<Outpoint, String> = HashMap::new();
+        for height in scan_from_height..=tip_height {
+            let block = get_block_with_transactions_at_height(client, height);
+            for transaction in get_transactions_of_block(block.tx) {
+                for outpoint in get_spent_outpoints(&transaction) {
+                    if watched_outpoints.contains(&outpoint) {
+                        spending_txid_by_outpoint.insert(outpoint, transaction.txid.clone());
+                    }
+                }
+                if self.watched_transactions.contains_key(&transaction.txid) {
+                    let status = TxStatus::Confirmed {
+                        height,
+                        blockhash: block.hash.clone(),
+                        time: block.time,
+                    };
+                    confirmed_statuses.insert(transaction.txid, status);
+                }
+            }
+        }
+
+        // fetched before updating anything so a failing call leaves the watcher as it was
+        let mut mempool_entries: HashMap<String, MempoolEntry> = HashMap::new();
+        for (txid, watched_transaction) in &self.watched_transactions {
+            let is_settled = confirmed_statuses.contains_key(txid)
+                || (matches!(watched_transaction.status, TxStatus::Confirmed { .. })
+                    && !reorged_out_txids.contains(txid))
+                || matches!(watched_transaction.status, TxStatus::Replaced { .. });
+            if !is_settled {
+                if let Some(entry) = get_mempool_entry(client, txid)? {
+                    mempool_entries.insert(txid.clone(), entry);
+                }
+            }
+        }
+        self.scanned_height = Some(tip_height);
+
+        let mut transitions = vec![];
+        for (txid, watched_transaction) in self.watched_transactions.iter_mut() {
+            let previous_status = watched_transaction.status.clone();
+            let is_reorged_out = reorged_out_txids.contains(txid);
+            let status = if let Some(status) = confirmed_statuses.remove(txid) {
+                status
+            } else if (matches!(previous_status, TxStatus::Confirmed { .. }) && !is_reorged_out)
+                || matches!(previous_status, TxStatus::Replaced { .. })
+            {
+                previous_status.clone()
+            } else if let Some(entry) = mempool_entries.remove(txid) {
+                watched_transaction
+                    .first_seen_time
+                    .get_or_insert(entry.time as i64);
+                if watched_transaction.spent_outpoints.is_empty() {
+                    watched_transaction.spent_outpoints =
+                        get_spent_outpoints_of_mempool_transaction(client, txid)
+                            .unwrap_or_default();
+                }
+                TxStatus::InMempool
+            } else if is_reorged_out {
+                TxStatus::ReorgedOut
+            } else if let Some(replaced_by) = watched_transaction
+                .spent_outpoints
+                .iter()
+                .find_map(|outpoint| spending_txid_by_outpoint.get(outpoint))
+            {
+                TxStatus::Replaced {
+                    replaced_by: Some(replaced_by.clone()),
+                }
+            } else if previous_status == TxStatus::InMempool {
+                match get_mempool_spending_txid(client, &watched_transaction.spent_outpoints) {
+                    Some(replaced_by) => TxStatus::Replaced {
+                        replaced_by: Some(replaced_by),
+                    },
+                    None => TxStatus::Dropped,
+                }
+            } else if previous_status == TxStatus::Unseen {
+                get_confirmed_status_with_txindex(client, txid).unwrap_or(TxStatus::Unseen)
+            } else {
+                previous_status.clone()
+            };
+
+            let (confirmations, first_block_time, target_block_time) = match &status {
+                TxStatus::Confirmed { height, time, .. } => {
+                    let confirmations = tip_height + 1 - height;
+                    let target_height = height + watched_transaction.target_confirmations - 1;
+                    let target_block_time = if target_height <= tip_height {
+                        Some(get_timestamp_of_block_at_height(client, target_height) as i64)
+                    } else {
+                        None
+                    };
+                    (confirmations, Some(*time as i64), target_block_time)
+                }
+                _ => (0, None, None),
+            };
+            let reached_target_confirmations =
+                confirmations >= watched_transaction.target_confirmations;
+            let is_newly_reached =
+                reached_target_confirmations && !watched_transaction.reached_target_confirmations;
+            watched_transaction.reached_target_confirmations = reached_target_confirmations;
+            watched_transaction.status = status.clone();
+            if status == previous_status && !is_newly_reached {
+                continue;
+            }
+
+            let first_seen_time = watched_transaction.first_seen_time;
+            transitions.push(TxStatusTransition {
+                txid: txid.clone(),
+                previous_status,
+                status,
+                confirmations,
+                target_confirmations: watched_transaction.target_confirmations,
+                time: now,
+                time_in_mempool: first_seen_time
+                    .map(|first_seen_time| first_block_time.unwrap_or(now) - first_seen_time),
+                time_to_confirm: first_seen_time.and_then(|first_seen_time| {
+                    target_block_time.map(|target_block_time| target_block_time - first_seen_time)
+                }),
+            });
+        }
+
+        if let Some(callback) = self.callback.as_mut() {
+            for transition in &transitions {
+                callback(transition);
+            }
+        }
+        Ok(transitions)
+    }
+
+    // Blocks, polling every `poll_interval`, until `is_settled`.
+    pub fn watch_until_settled(
+        &mut self,
+        client: &Client,
+        poll_interval: Duration,
+    ) -> Result<(), jsonrpc::Error> {
+        loop {
+            self.poll(client)?;
+            if self.is_settled() {
+                return Ok(());
+            }
+            sleep(poll_interval);
+        }
+    }
+}
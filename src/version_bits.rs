@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::{
+    get_block_hash::GetBlockHashCommand,
+    get_block_header::{GetBlockHeaderCommand, GetBlockHeaderCommandResponse},
+    CallableCommand,
+};
+use serde::Deserialize;
+
+use crate::mining_pool::{get_block_pool_from_coinbase_transaction, UNKNOWN_POOL_NAME};
+use crate::network::get_network;
+use crate::{
+    get_block_height, get_block_with_transactions_at_height, get_transactions_of_block, Client,
+    PoolDefinitions,
+};
+
+// BIP9: the top 3 bits of the version must be 001 for the remaining 29 bits to be signals.
+const VERSION_BITS_TOP_MASK: u32 = 0xE000_0000;
+const VERSION_BITS_TOP_BITS: u32 = 0x2000_0000;
+const VERSION_BITS_COUNT: u8 = 29;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Bip9Statistics {
+    pub period: u64,
+    // not present once the deployment is locked in or active
+    pub threshold: Option<u64>,
+    pub elapsed: u64,
+    pub count: u64,
+    pub possible: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Bip9Deployment {
+    pub bit: Option<u8>,
+    pub start_time: i64,
+    pub timeout: i64,
+    pub min_activation_height: Option<u64>,
+    // one of "defined", "started", "locked_in", "active", "failed"
+    pub status: String,
+    pub since: u64,
+    pub statistics: Option<Bip9Statistics>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Deployment {
+    // one of "buried", "bip9"
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub height: Option<u64>,
+    pub active: bool,
+    pub bip9: Option<Bip9Deployment>,
+}
+
+#[derive(Deserialize)]
+struct GetDeploymentInfoResponse {
+    deployments: HashMap<String, Deployment>,
+}
+
+#[derive(Deserialize)]
+struct GetBlockchainInfoSoftforksResponse {
+    softforks: Option<HashMap<String, Deployment>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockVersion {
+    pub height: u64,
+    pub version: u32,
+    pub signaled_bits: Vec<u8>,
+    pub pool_name: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BitSignaling {
+    pub bit: u8,
+    pub blocks_count: u64,
+    // between 0.0 and 1.0, of all the blocks in the range
+    pub share: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolSignaling {
+    pub pool_name: String,
+    pub blocks_count: u64,
+    pub signaling_blocks_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeploymentSignaling {
+    pub name: String,
+    pub bit: Option<u8>,
+    pub status: String,
+    pub active: bool,
+    pub period: Option<u64>,
+    pub threshold: Option<u64>,
+    // of the current signaling period, as reported by the node
+    pub period_elapsed_blocks_count: Option<u64>,
+    pub period_signaling_blocks_count: Option<u64>,
+    // of the scanned range
+    pub signaling_blocks_count: u64,
+    // between 0.0 and 1.0, None for an empty range
+    pub signaling_share: Option<f64>,
+    // None if the deployment can't, or isn't on track to, lock in at the end of the current period
+    pub projected_lock_in_height: Option<u64>,
+    // empty unless pool definitions are passed
+    pub signaling_by_pool: Vec<PoolSignaling>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VersionBitsReport {
+    pub start_height: u64,
+    pub end_height: u64,
+    pub blocks_count: u64,
+    // blocks using the BIP9 version scheme (top bits 001)
+    pub version_bits_blocks_count: u64,
+    // only bits signaled by at least one block
+    pub signaling_by_bit: Vec<BitSignaling>,
+    pub deployments: Vec<DeploymentSignaling>,
+}
+
+pub fn get_signaled_bits(version: u32) -> Vec<u8> {
+    if version & VERSION_BITS_TOP_MASK != VERSION_BITS_TOP_BITS {
+        return vec![];
+    }
+    (0..VERSION_BITS_COUNT)
+        .filter(|bit| version & (1 << bit) != 0)
+        .collect()
+}
+
+// Uses getdeploymentinfo, falling back on the softforks of getblockchaininfo for nodes older than
+// v23.
+pub fn get_deployments(client: &Client) -> HashMap<String, Deployment> {
+    let deployment_info: Result<GetDeploymentInfoResponse, jsonrpc::Error> =
+        client.call("getdeploymentinfo", &[]);
+    match deployment_info {
+        Ok(deployment_info) => deployment_info.deployments,
+        Err(_) => {
+            let blockchain_info: GetBlockchainInfoSoftforksResponse =
+                client.call("getblockchaininfo", &[]).unwrap();
+            blockchain_info.softforks.unwrap_or_default()
+        }
+    }
+}
+
+pub fn get_block_version_at_height(
+    client: &Client,
+    height: u64,
+    definitions: Option<&PoolDefinitions>,
+) -> BlockVersion {
+    // The pool can only be found from the coinbase, so fetch the whole block if we need it.
+    let (version, pool_name) = match definitions {
+        Some(definitions) => {
+            let block = get_block_with_transactions_at_height(client, height);
+            let transactions = get_transactions_of_block(block.tx);
+            let block_pool = get_block_pool_from_coinbase_transaction(
+                definitions,
+                height,
+                block.hash,
+                block.time,
+                transactions.first().unwrap(),
+            );
+            (block.version, block_pool.pool_name)
+        }
+        None => {
+            let bitcoind_request_client = &client.bitcoind_request_client;
+            let blockhash = GetBlockHashCommand::new(height)
+                .call(bitcoind_request_client)
+                .unwrap()
+                .0;
+            let block_header = GetBlockHeaderCommand::new(blockhash).call(bitcoind_request_client);
+            let version = match block_header.unwrap() {
+                GetBlockHeaderCommandResponse::BlockHeader(block_header) => block_header.version,
+                GetBlockHeaderCommandResponse::BlockHash(_) => unreachable!(),
+            };
+            (version, None)
+        }
+    };
+    let version = version as u32;
+    BlockVersion {
+        height,
+        version,
+        signaled_bits: get_signaled_bits(version),
+        pool_name,
+    }
+}
+
+// Heights of the current signaling period, from its first block up to the most recent block. The
+// period length depends on the network (144 blocks on regtest), so it's taken from the statistics
+// of a started deployment, and from the network when no deployment is started.
+pub fn get_current_signaling_period_heights(client: &Client) -> RangeInclusive<u64> {
+    let tip_height = get_block_height(client);
+    let statistics = get_deployments(client)
+        .into_values()
+        .find_map(|deployment| {
+            deployment
+                .bip9
+                .and_then(|bip9| bip9.statistics)
+                .filter(|statistics| statistics.period > 0)
+        });
+    // the statistics are of the period the next block belongs to
+    let next_height = tip_height + 1;
+    let start_height = match statistics {
+        Some(statistics) => next_height - statistics.elapsed,
+        None => {
+            let period = get_network(client).signaling_period();
+            next_height - next_height % period
+        }
+    };
+    start_height..=tip_height
+}
+
+fn get_projected_lock_in_height(deployment: &Deployment, tip_height: u64) -> Option<u64> {
+    let bip9 = deployment.bip9.as_ref()?;
+    if bip9.status != "started" {
+        return None;
+    }
+    let statistics = bip9.statistics.as_ref()?;
+    let threshold = statistics.threshold?;
+    let remaining_blocks_count = statistics.period - statistics.elapsed;
+    let end_of_period_height = tip_height + remaining_blocks_count;
+    if statistics.count >= threshold {
+        return Some(end_of_period_height);
+    }
+    if statistics.elapsed == 0 || statistics.count + remaining_blocks_count < threshold {
+        return None;
+    }
+    // Assume the rest of the period signals at the same rate as so far.
+    let projected_count =
+        statistics.count as f64 / statistics.elapsed as f64 * statistics.period as f64;
+    if projected_count >= threshold as f64 {
+        Some(end_of_period_height)
+    } else {
+        None
+    }
+}
+
+fn get_signaling_by_pool(block_versions: &[BlockVersion], bit: u8) -> Vec<PoolSignaling> {
+    let mut signaling_by_pool: HashMap<&str, PoolSignaling> = HashMap::new();
+    for block_version in block_versions {
+        let pool_name = block_version
+            .pool_name
+            .as_deref()
+            .unwrap_or(UNKNOWN_POOL_NAME);
+        let pool_signaling = signaling_by_pool
+            .entry(pool_name)
+            .or_insert_with(|| PoolSignaling {
+                pool_name: pool_name.to_string(),
+                blocks_count: 0,
+                signaling_blocks_count: 0,
+            });
+        pool_signaling.blocks_count += 1;
+        if block_version.signaled_bits.contains(&bit) {
+            pool_signaling.signaling_blocks_count += 1;
+        }
+    }
+    let mut signaling_by_pool: Vec<PoolSignaling> = signaling_by_pool.into_values().collect();
+    signaling_by_pool.sort_by(|a, b| {
+        b.blocks_count
+            .cmp(&a.blocks_count)
+            .then_with(|| a.pool_name.cmp(&b.pool_name))
+    });
+    signaling_by_pool
+}
+
+pub fn get_version_bits_report(
+    block_versions: &[BlockVersion],
+    deployments: &HashMap<String, Deployment>,
+    tip_height: u64,
+) -> VersionBitsReport {
+    let blocks_count = block_versions.len() as u64;
+    let mut blocks_count_by_bit: HashMap<u8, u64> = HashMap::new();
+    for block_version in block_versions {
+        for bit in &block_version.signaled_bits {
+            *blocks_count_by_bit.entry(*bit).or_insert(0) += 1;
+        }
+    }
+    let mut signaling_by_bit: Vec<BitSignaling> = blocks_count_by_bit
+        .iter()
+        .map(|(bit, bit_blocks_count)| BitSignaling {
+            bit: *bit,
+            blocks_count: *bit_blocks_count,
+            share: *bit_blocks_count as f64 / blocks_count as f64,
+        })
+        .collect();
+    signaling_by_bit.sort_by_key(|bit_signaling| bit_signaling.bit);
+
+    let has_pools = block_versions
+        .iter()
+        .any(|block_version| block_version.pool_name.is_some());
+    let mut deployment_signalings: Vec<DeploymentSignaling> = deployments
+        .iter()
+        .map(|(name, deployment)| {
+            let bip9 = deployment.bip9.as_ref();
+            let statistics = bip9.and_then(|bip9| bip9.statistics.as_ref());
+            let bit = bip9.and_then(|bip9| bip9.bit);
+            let signaling_blocks_count = bit
+                .map(|bit| *blocks_count_by_bit.get(&bit).unwrap_or(&0))
+                .unwrap_or(0);
+            let signaling_by_pool = match bit {
+                Some(bit) if has_pools => get_signaling_by_pool(block_versions, bit),
+                _ => vec![],
+            };
+            DeploymentSignaling {
+                name: name.clone(),
+                bit,
+                status: bip9
+                    .map(|bip9| bip9.status.clone())
+                    .unwrap_or_else(|| deployment.type_.clone()),
+                active: deployment.active,
+                period: statistics.map(|statistics| statistics.period),
+                threshold: statistics.and_then(|statistics| statistics.threshold),
+                period_elapsed_blocks_count: statistics.map(|statistics| statistics.elapsed),
+                period_signaling_blocks_count: statistics.map(|statistics| statistics.count),
+                signaling_blocks_count,
+                signaling_share: (blocks_count > 0)
+                    .then(|| signaling_blocks_count as f64 / blocks_count as f64),
+                projected_lock_in_height: get_projected_lock_in_height(deployment, tip_height),
+                signaling_by_pool,
+            }
+        })
+        .collect();
+    deployment_signalings.sort_by(|a, b| a.name.cmp(&b.name));
+
+    VersionBitsReport {
+        start_height: block_versions.first().map(|b| b.height).unwrap_or(0),
+        end_height: block_versions.last().map(|b| b.height).unwrap_or(0),
+        blocks_count,
+        version_bits_blocks_count: block_versions
+            .iter()
+            .filter(|block_version| {
+                block_version.version & VERSION_BITS_TOP_MASK == VERSION_BITS_TOP_BITS
+            })
+            .count() as u64,
+        signaling_by_bit,
+        deployments: deployment_signalings,
+    }
+}
+
+// Pass pool definitions to break down signaling by mining pool, at the cost of fetching every
+// block in the range.
+// takes a long time
+pub fn get_version_bits_report_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    definitions: Option<&PoolDefinitions>,
+) -> VersionBitsReport {
+    let block_versions: Vec<BlockVersion> = heights
+        .map(|height| get_block_version_at_height(client, height, definitions))
+        .collect();
+    let deployments = get_deployments(client);
+    let tip_height = get_block_height(client);
+    get_version_bits_report(&block_versions, &deployments, tip_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIP_HEIGHT: u64 = 800_000;
+
+    // mainnet period and threshold (90%) of the deployments since taproot
+    fn started_deployment(elapsed: u64, count: u64) -> Deployment {
+        Deployment {
+            type_: "bip9".to_string(),
+            height: None,
+            active: false,
+            bip9: Some(Bip9Deployment {
+                bit: Some(2),
+                start_time: 0,
+                timeout: i64::MAX,
+                min_activation_height: Some(0),
+                status: "started".to_string(),
+                since: 798_336,
+                statistics: Some(Bip9Statistics {
+                    period: 2016,
+                    threshold: Some(1815),
+                    elapsed,
+                    count,
+                    possible: Some(true),
+                }),
+            }),
+        }
+    }
+
+    #[test]
+    fn signaled_bits_need_the_version_bits_top_bits() {
+        assert_eq!(get_signaled_bits(0x2000_0000), Vec::<u8>::new());
+        assert_eq!(get_signaled_bits(0x2000_0004), vec![2]);
+        assert_eq!(get_signaled_bits(0x3000_0001), vec![0, 28]);
+        assert_eq!(
+            get_signaled_bits(0x3fff_ffff),
+            (0..VERSION_BITS_COUNT).collect::<Vec<u8>>()
+        );
+        // pre BIP9 versions, and top bits other than 001
+        assert_eq!(get_signaled_bits(4), Vec::<u8>::new());
+        assert_eq!(get_signaled_bits(0x4000_0004), Vec::<u8>::new());
+        assert_eq!(get_signaled_bits(0xe000_0004), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn lock_in_is_projected_at_the_end_of_the_period_once_the_threshold_is_reached() {
+        let end_of_period_height = TIP_HEIGHT + 2016 - 1900;
+        assert_eq!(
+            get_projected_lock_in_height(&started_deployment(1900, 1815), TIP_HEIGHT),
+            Some(end_of_period_height)
+        );
+    }
+
+    #[test]
+    fn lock_in_is_projected_from_the_signaling_rate_so_far() {
+        let end_of_period_height = TIP_HEIGHT + 2016 - 1000;
+        // 95% of the blocks so far
+        assert_eq!(
+            get_projected_lock_in_height(&started_deployment(1000, 950), TIP_HEIGHT),
+            Some(end_of_period_height)
+        );
+        // 90%, which projects to 1814.4 blocks
+        assert_eq!(
+            get_projected_lock_in_height(&started_deployment(1000, 900), TIP_HEIGHT),
+            None
+        );
+        // can't reach the threshold anymore, even if every remaining block signals
+        assert_eq!(
+            get_projected_lock_in_height(&started_deployment(1000, 700), TIP_HEIGHT),
+            None
+        );
+        // nothing to project from at the start of a period
+        assert_eq!(
+            get_projected_lock_in_height(&started_deployment(0, 0), TIP_HEIGHT),
+            None
+        );
+    }
+
+    #[test]
+    fn lock_in_is_only_projected_for_started_deployments() {
+        let mut locked_in = started_deployment(1900, 1815);
+        locked_in.bip9.as_mut().unwrap().status = "locked_in".to_string();
+        assert_eq!(get_projected_lock_in_height(&locked_in, TIP_HEIGHT), None);
+
+        let mut without_threshold = started_deployment(1900, 1815);
+        without_threshold
+            .bip9
+            .as_mut()
+            .unwrap()
+            .statistics
+            .as_mut()
+            .unwrap()
+            .threshold = None;
+        assert_eq!(
+            get_projected_lock_in_height(&without_threshold, TIP_HEIGHT),
+            None
+        );
+
+        let buried = Deployment {
+            type_: "buried".to_string(),
+            height: Some(481_824),
+            active: true,
+            bip9: None,
+        };
+        assert_eq!(get_projected_lock_in_height(&buried, TIP_HEIGHT), None);
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use bitcoind_request::command::get_block::DecodeRawTransactionResponse;
+
+use crate::fee_rates::FeeRateInterval;
+use crate::prevouts::{PrevoutResolver, DEFAULT_PREVOUT_CACHE_CAPACITY};
+use crate::script::decode_hex;
+use crate::script_types::{get_script_type, ScriptType};
+use crate::{get_block_heights_over_last_24_hours, Client};
+
+pub const MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+const WITNESS_SCALE_FACTOR: u64 = 4;
+
+// All sizes in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionWeight {
+    // serialized with the witness data
+    pub size: u64,
+    // serialized without the witness data (stripped size)
+    pub base_size: u64,
+    // size - base_size, the segwit marker and flag included
+    pub witness_size: u64,
+    pub weight: u64,
+    // in vbytes
+    pub vsize: u64,
+    // in vbytes, size - vsize
+    pub discount_savings: u64,
+    // one per input, without the segwit marker and flag
+    pub input_witness_sizes: Vec<u64>,
+}
+
+// All sizes in bytes, and savings in vbytes.
+#[derive(Debug, Clone)]
+pub struct BlockSpaceWindow {
+    // unix timestamp of the start of the interval
+    pub start_time: u64,
+    pub blocks_count: u64,
+    // includes coinbase transactions
+    pub transactions_count: u64,
+    pub size: u64,
+    pub witness_size: u64,
+    pub weight: u64,
+    pub vsize: u64,
+    // between 0.0 and 100.0, weight of the transactions over MAX_BLOCK_WEIGHT for every block
+    pub percent_of_max_weight: f64,
+    pub discount_savings: u64,
+    // Savings of the witnesses of inputs spending segwit v0 outputs, nested ones included. None
+    // when the spent outputs of a block of the window couldn't be resolved, as the split needs
+    // them while the rest doesn't.
+    pub segwit_v0_discount_savings: Option<u64>,
+    // savings of the witnesses of inputs spending taproot outputs, None like above
+    pub taproot_discount_savings: Option<u64>,
+    // transactions whose hex couldn't be parsed, counted with the size and weight from getblock.
+    // Leaves the savings split as None, as it needs the witness size of every input.
+    pub unparsed_transactions_count: u64,
+}
+
+impl BlockSpaceWindow {
+    fn new(start_time: u64) -> Self {
+        BlockSpaceWindow {
+            start_time,
+            blocks_count: 0,
+            transactions_count: 0,
+            size: 0,
+            witness_size: 0,
+            weight: 0,
+            vsize: 0,
+            percent_of_max_weight: 0.0,
+            discount_savings: 0,
+            segwit_v0_discount_savings: Some(0),
+            taproot_discount_savings: Some(0),
+            unparsed_transactions_count: 0,
+        }
+    }
+}
+
+struct TransactionReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> TransactionReader<'a> {
+    fn skip(&mut self, length: usize) -> Option<()> {
+        let position = self.position.checked_add(length)?;
+        if position > self.bytes.len() {
+            return None;
+        }
+        self.position = position;
+        Some(())
+    }
+    fn read_u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+    // CompactSize
+    fn read_var_int(&mut self) -> Option<u64> {
+        let length = match self.read_u8()? {
+            0xfd => 2,
+            0xfe => 4,
+            0xff => 8,
+            byte => return Some(byte as u64),
+        };
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.checked_add(length)?)?;
+        self.position += length;
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |value, byte| (value << 8) | *byte as u64),
+        )
+    }
+    fn skip_var_bytes(&mut self) -> Option<()> {
+        let length = self.read_var_int()?;
+        self.skip(usize::try_from(length).ok()?)
+    }
+}
+
+// Walks through the serialized transaction, as returned in the hex field of getrawtransaction and
+// getblock. None when the hex isn't a valid transaction.
+pub fn get_transaction_weight(hex: &str) -> Option<TransactionWeight> {
+    let bytes = decode_hex(hex);
+    let mut reader = TransactionReader {
+        bytes: &bytes,
+        position: 0,
+    };
+    // version
+    reader.skip(4)?;
+    let has_witness = bytes.get(4) == Some(&0x00) && bytes.get(5) == Some(&0x01);
+    if has_witness {
+        // marker and flag
+        reader.skip(2)?;
+    }
+    let inputs_count = reader.read_var_int()?;
+    for _ in 0..inputs_count {
+        // outpoint
+        reader.skip(36)?;
+        // scriptSig
+        reader.skip_var_bytes()?;
+        // sequence
+        reader.skip(4)?;
+    }
+    let outputs_count = reader.read_var_int()?;
+    for _ in 0..outputs_count {
+        // value
+        reader.skip(8)?;
+        // scriptPubKey
+        reader.skip_var_bytes()?;
+    }
+    let mut input_witness_sizes = vec![];
+    if has_witness {
+        for _ in 0..inputs_count {
+            let start = reader.position;
+            let stack_elements_count = reader.read_var_int()?;
+            for _ in 0..stack_elements_count {
+                reader.skip_var_bytes()?;
+            }
+            input_witness_sizes.push((reader.position - start) as u64);
+        }
+    } else {
+        input_witness_sizes = vec![0; inputs_count as usize];
+    }
+    // locktime
+    reader.skip(4)?;
+    if reader.position != bytes.len() {
+        return None;
+    }
+
+    let size = bytes.len() as u64;
+    let witness_size = if has_witness {
+        2 + input_witness_sizes.iter().sum::<u64>()
+    } else {
+        0
+    };
+    let base_size = size - witness_size;
+    let weight = base_size * (WITNESS_SCALE_FACTOR - 1) + size;
+    let vsize = weight.div_ceil(WITNESS_SCALE_FACTOR);
+    Some(TransactionWeight {
+        size,
+        base_size,
+        witness_size,
+        weight,
+        vsize,
+        discount_savings: size - vsize,
+        input_witness_sizes,
+    })
+}
+
+// From the size and weight fields of getblock, without the witness size of each input.
+fn get_transaction_weight_from_response(
+    transaction: &DecodeRawTransactionResponse,
+) -> TransactionWeight {
+    let base_size =
+        transaction.weight.saturating_sub(transaction.size) / (WITNESS_SCALE_FACTOR - 1);
+    TransactionWeight {
+        size: transaction.size,
+        base_size,
+        witness_size: transaction.size.saturating_sub(base_size),
+        weight: transaction.weight,
+        vsize: transaction.vsize,
+        discount_savings: transaction.size.saturating_sub(transaction.vsize),
+        input_witness_sizes: vec![],
+    }
+}
+
+// In vbytes, the witness of a single input.
+fn get_witness_discount_savings(witness_size: u64) -> u64 {
+    witness_size - witness_size / WITNESS_SCALE_FACTOR
+}
+
+// takes a long time
+// Groups the blocks by their timestamp. Ordered by time. Savings are split by the type of output
+// spent, with the PrevoutResolver.
+pub fn get_block_space_windows_for_block_range(
+    client: &Client,
+    heights: RangeInclusive<u64>,
+    interval: FeeRateInterval,
+) -> Vec<BlockSpaceWindow> {
+    let mut prevout_resolver = PrevoutResolver::new(DEFAULT_PREVOUT_CACHE_CAPACITY);
+    let mut windows: BTreeMap<u64, BlockSpaceWindow> = BTreeMap::new();
+    for height in heights {
+        let block = prevout_resolver.get_block_with_prevouts_at_height(client, height);
+        let start_time = block.time - block.time % interval.seconds();
+        let window = windows
+            .entry(start_time)
+            .or_insert_with(|| BlockSpaceWindow::new(start_time));
+        window.blocks_count += 1;
+        let block_prevouts = match &block.prevouts {
+            Ok(block_prevouts) => Some(block_prevouts),
+            Err(_) => {
+                window.segwit_v0_discount_savings = None;
+                window.taproot_discount_savings = None;
+                None
+            }
+        };
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let transaction_weight = match get_transaction_weight(&transaction.hex) {
+                Some(transaction_weight) => transaction_weight,
+                None => {
+                    window.unparsed_transactions_count += 1;
+                    window.segwit_v0_discount_savings = None;
+                    window.taproot_discount_savings = None;
+                    get_transaction_weight_from_response(transaction)
+                }
+            };
+            window.transactions_count += 1;
+            window.size += transaction_weight.size;
+            window.witness_size += transaction_weight.witness_size;
+            window.weight += transaction_weight.weight;
+            window.vsize += transaction_weight.vsize;
+            window.discount_savings += transaction_weight.discount_savings;
+            // the coinbase has no prevouts, and its witness only holds the witness reserved value
+            let prevouts = match block_prevouts {
+                Some(block_prevouts) if index > 0 => &block_prevouts[index],
+                _ => continue,
+            };
+            for (prevout, witness_size) in
+                prevouts.iter().zip(&transaction_weight.input_witness_sizes)
+            {
+                let savings = get_witness_discount_savings(*witness_size);
+                let split_savings = match get_script_type(&decode_hex(&prevout.script_pub_key_hex))
+                {
+                    ScriptType::P2tr => &mut window.taproot_discount_savings,
+                    // P2SH outputs with a witness are nested segwit
+                    ScriptType::P2wpkh | ScriptType::P2wsh | ScriptType::P2sh => {
+                        &mut window.segwit_v0_discount_savings
+                    }
+                    _ => continue,
+                };
+                if let Some(split_savings) = split_savings {
+                    *split_savings += savings;
+                }
+            }
+        }
+    }
+    windows
+        .into_values()
+        .map(|mut window| {
+            window.percent_of_max_weight =
+                window.weight as f64 / (window.blocks_count * MAX_BLOCK_WEIGHT) as f64 * 100.0;
+            window
+        })
+        .collect()
+}
+
+// takes a long time
+pub fn get_block_space_windows_over_last_24_hours(
+    client: &Client,
+    interval: FeeRateInterval,
+) -> Vec<BlockSpaceWindow> {
+    let heights = get_block_heights_over_last_24_hours(client);
+    get_block_space_windows_for_block_range(client, heights, interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // coinbase of the genesis block, txid 4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b
+    const LEGACY_TRANSACTION_HEX: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+    // native P2WPKH example of BIP143, spending a P2PK output and a P2WPKH output
+    const SEGWIT_TRANSACTION_HEX: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeeb635711000000";
+    // taproot key path spend: one input with a 64 bytes signature as its only witness element,
+    // paying to a P2TR output
+    const TAPROOT_TRANSACTION_HEX: &str = "02000000000101abababababababababababababababababababababababababababababababab0000000000fdffffff01e803000000000000225120cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd0140efefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefefef00000000";
+
+    #[test]
+    fn legacy_transaction_has_no_discount() {
+        assert_eq!(
+            get_transaction_weight(LEGACY_TRANSACTION_HEX),
+            Some(TransactionWeight {
+                size: 204,
+                base_size: 204,
+                witness_size: 0,
+                weight: 816,
+                vsize: 204,
+                discount_savings: 0,
+                input_witness_sizes: vec![0],
+            })
+        );
+    }
+
+    #[test]
+    fn segwit_transaction_weight() {
+        assert_eq!(
+            get_transaction_weight(SEGWIT_TRANSACTION_HEX),
+            Some(TransactionWeight {
+                size: 343,
+                base_size: 233,
+                witness_size: 110,
+                weight: 1042,
+                vsize: 261,
+                discount_savings: 82,
+                // the P2PK input only has the empty witness stack count
+                input_witness_sizes: vec![1, 107],
+            })
+        );
+    }
+
+    #[test]
+    fn taproot_transaction_weight() {
+        assert_eq!(
+            get_transaction_weight(TAPROOT_TRANSACTION_HEX),
+            Some(TransactionWeight {
+                size: 162,
+                base_size: 94,
+                witness_size: 68,
+                weight: 444,
+                vsize: 111,
+                discount_savings: 51,
+                input_witness_sizes: vec![66],
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_transaction_has_no_weight() {
+        // truncated
+        assert_eq!(get_transaction_weight(&SEGWIT_TRANSACTION_HEX[..200]), None);
+        // trailing bytes
+        assert_eq!(
+            get_transaction_weight(&format!("{}00", LEGACY_TRANSACTION_HEX)),
+            None
+        );
+        // a script length of u64::MAX
+        assert_eq!(
+            get_transaction_weight(
+                "0100000001000000000000000000000000000000000000000000000000000000000000000000000000ffffffffffffffffff"
+            ),
+            None
+        );
+    }
+}